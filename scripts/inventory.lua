@@ -5,10 +5,14 @@ InventorySystem = {
 function InventorySystem:spawn(o)
 	self.inventories[o.id] = {show = false, items = {}}
 end
+function InventorySystem:despawn(id)
+	self.inventories[id] = nil
+end
 function InventorySystem:tick() 
-	for k,v in pairs(self.inventories) do
+	for id in world:members("Inventory") do
+		local v = self.inventories[id]
 		if v.show then
-			print("entity #"..k.."'s inventory: ")
+			print("entity #"..id.."'s inventory: ")
 			tprint(v.items, 0)
 		end
 	end
@@ -4,6 +4,9 @@ InfoSystem = {
 function InfoSystem:spawn(o)
 	self.names[o.id] = o.name
 end
+function InfoSystem:despawn(id)
+	self.names[id] = nil
+end
 function InfoSystem:get(id) 
 	return {name = self.names[id]}
 end
@@ -27,37 +30,42 @@ player = {
 	},
 	---[[
 	Physics = {
-		position = {x = 32, y = 32},
+		position = {x = 96, y = 64},
 		solid = true,
-		dynamic = false,
-		velocity = {x = 2, y = 2},
+		restitution = 1,
+		velocity = {x = 120, y = 120}, --pixels per second
 		acceleration = {x = 0, y = 0},
 		angle = 45.0
 	},
 	--]]
+	--follows Physics, and anything parented to the player follows it
+	Transform = {},
 	Collision = {
+		shape = "aabb",
+		width = 32,
+		height = 32,
 		respond_solid = true,
-		--solid type?
 		callback = function(self, other)
-			print("fucka you", world.get("Info", other).name)
-			world.system_update({"PhysicsComponent"}, function(p) p.velocity.x = -1 end)
+			local info = world:get("Info", other)
+			print("fucka you", info and info.name or other)
+			Camera.shake(camera_id, 3, 0.2)
 		end
 	},
 	Input = {
 		callback = function(self, input)
-			world.entity_update(self, {"Position"}, function(pos) 
-				if input.keyboard.Left then
-					pos.x = pos.x - 1
-				end
-				if input.keyboard.Right then
-					pos.x = pos.x + 1
-				end
-			end)
-			if input.keyboard.A then
-				print("A????")
+			local x, y = 0, 0
+			if input.held("move_left") then x = x - 1 end
+			if input.held("move_right") then x = x + 1 end
+			if input.held("move_up") then y = y - 1 end
+			if input.held("move_down") then y = y + 1 end
+			local pad = input.controllers[0]
+			if pad and pad.axes.leftx then
+				x = x + pad.axes.leftx
+				y = y + (pad.axes.lefty or 0)
 			end
-			if input.keybaord.I then
-				InventorySystem:show(self, ~InventorySystem:is_show(self))
+			Physics.apply_force(self, x * 400, y * 400)
+			if input.pressed("toggle_inventory") then
+				InventorySystem:show(self, not InventorySystem:is_show(self))
 			end
 		end
 	},
@@ -70,15 +78,11 @@ player = {
 PlayerGlowSystem = {
 	counter = 0
 }
-function PlayerGlowSystem:tick() 
-	self.counter = self.counter + 1 / (2 * math.pi);
+function PlayerGlowSystem:tick(dt) 
+	self.counter = self.counter + 60 * dt / (2 * math.pi);
 	--print(1 + math.sin(self.counter))
-	world:entity_update(player_id, {'Render'}, function(r)
-		offset = math.floor(16 * (1 + math.sin(self.counter / 2)))
-		r.width = 32 + offset
-		r.height = 32 + offset
-		return r
-	end)
+	local offset = math.floor(16 * (1 + math.sin(self.counter / 2)))
+	Render.set_size(player_id, 32 + offset, 32 + offset)
 end
 world:add_system(PlayerGlowSystem, "PlayerGlowSystem", "PlayerGlowSystem")
 
@@ -95,6 +99,22 @@ end
 setmetatable(_G, {__index=ReadOnly, __newindex=check})
 
 ReadOnly.player_id = world:spawn(player)
+
+--follows the player around; +/- zoom
+ReadOnly.camera_id = world:spawn({
+	Camera = {
+		follow = player_id,
+		smoothing = 6,
+		deadzone = {width = 64, height = 48},
+	},
+	Input = {
+		callback = function(self, input)
+			local zoom = Camera.get_zoom(self)
+			if input.pressed("zoom_in") then Camera.set_zoom(self, zoom * 1.25) end
+			if input.pressed("zoom_out") then Camera.set_zoom(self, zoom / 1.25) end
+		end
+	}
+})
 print("player id:", player_id)
 tprint(world:get("Info", player_id))
 
@@ -111,61 +131,48 @@ PlayerFollowerSystem = {
 	currently_lit = 1,
 }
 for i = 1,9 do
-	table.insert(PlayerFollowerSystem.ids, world:spawn({Render={sprite="player_main", animations={idle={frame_width=32}}, animation="idle", z_index=-i, x = 0, y = 0}}))
+	table.insert(PlayerFollowerSystem.ids, world:spawn({Render={sprite="player_main", animations={idle={frame_width=32}}, animation="idle", z_index=-i}, Transform={}}))
 end
-function PlayerFollowerSystem:tick()
+function PlayerFollowerSystem:tick(dt)
 	for i = 1,10 do
-		world:entity_update(self.ids[i], {'Render'}, function(r) r.animations["idle"].current_frame = 1 return r end)
+		Render.set_frame(self.ids[i], 1)
 	end
-	self.currently_lit = self.currently_lit + 15/60 --1.5 revolutions per second
+	self.currently_lit = self.currently_lit + 15 * dt --1.5 revolutions per second
 	if math.floor(self.currently_lit) > 10 then
 		self.currently_lit = 1
 	end
-	world:entity_update(self.ids[math.floor(self.currently_lit)], {'Render'}, function(r) r.animations["idle"].current_frame = 2 return r end)
+	Render.set_frame(self.ids[math.floor(self.currently_lit)], 2)
 
 	for f = 2,10 do
-		world:entity_update(self.ids[f], {"Render"}, function(p) 
-			local p2 = world:get("Render", self.ids[f - 1])
-			local distance = math.sqrt((p2.x - p.x)^2 + (p2.y - p.y)^2)
-			local direction = math.atan(p2.y - p.y, p2.x - p.x)
-			p.x = p.x + math.cos(direction) * distance / self.smoothing
-			p.y = p.y + math.sin(direction) * distance / self.smoothing
-		return p end)
+		local x, y = Transform.get_world_position(self.ids[f])
+		local x2, y2 = Transform.get_world_position(self.ids[f - 1])
+		local distance = math.sqrt((x2 - x)^2 + (y2 - y)^2)
+		local direction = math.atan(y2 - y, x2 - x)
+		Transform.set_position(self.ids[f], x + math.cos(direction) * distance / self.smoothing, y + math.sin(direction) * distance / self.smoothing)
 	end
 end
 world:add_system(PlayerFollowerSystem, "PlayerFollowerSystem", "PlayerFollowerSystem")
 
-PlayerBoundsSystem = {}
-function PlayerBoundsSystem:tick()
-	world:entity_update(player_id, {"Physics"}, function(p) 
-		--[[
-		if p.position.x + 16 > 640 then 
-			p.position.x = 640 - 16
-			p.velocity.x = -math.random(4)
-		end
-		if p.position.x - 16 < 0 then
-			p.position.x = 0 + 16
-			p.velocity.x = math.random(4)
-		end
-		if p.position.y + 16 > 400 then 
-			p.position.y = 400 - 16
-			p.velocity.y = -math.random(4)
-		end
-		if p.position.y - 16 < 0 then
-			p.position.y = 0 + 16
-			p.velocity.y = math.random(4)
-		end
-		--]]
-		if p.position.x + 16 > 640 or p.position.x - 16 < 0 then
-			p.velocity.x = p.velocity.x * -1
-		end
-		if p.position.y + 16 > 400 or p.position.y - 16 < 0 then
-			p.velocity.y = p.velocity.y * -1
-		end
-		return p
-	end)
+--walls just outside the screen for the player to bounce off
+for _, wall in ipairs{
+	{x = 320, y = -16, width = 704, height = 32},
+	{x = 320, y = 416, width = 704, height = 32},
+	{x = -16, y = 200, width = 32, height = 400},
+	{x = 656, y = 200, width = 32, height = 400},
+} do
+	world:spawn{
+		Physics = {position = {x = wall.x, y = wall.y}, solid = true, kind = "static"},
+		Collision = {width = wall.width, height = wall.height},
+	}
 end
-world:add_system(PlayerBoundsSystem, "PlayerBoundsSystem", "PlayerBoundsSystem")
+
+--typed out a few characters at a time, in the bottom left corner
+world:spawn{Text = {
+	text = "welcome to luasys! press ` for the lua console, and click things in it to get their ids",
+	x = 12, y = 344, width = 252, text_speed = 30, screen_space = true,
+	background_color = {r = 0, g = 0, b = 0, a = 191},
+	border_width = 2,
+}}
 
 PlayerMouseSystem = {}
 function PlayerMouseSystem:tick()
@@ -177,11 +184,13 @@ function PlayerMouseSystem:tick()
 end
 --world:add_system(PlayerMouseSystem, "PlayerMouseSystem", "PlayerMouseSystem")
 
-world:system_update({"Info"}, function(i)
+--a setter that errors hands back a Lua error naming the systems it was updating, instead of taking the game down
+local ok, err = pcall(world.system_update, world, {"Info"}, function(i)
 	world:entity_update(player_id, {"Info"}, function(j) j.name = "???" return j end)
 	i = i .. i
 	return i
 end)
+print(ok, err)
 tprint(world:get("Info", player_id))
 
 EnemySystem = {
@@ -190,6 +199,9 @@ EnemySystem = {
 function EnemySystem:spawn(o)
 	self.enemies[o.id] = {}
 end
+function EnemySystem:despawn(id)
+	self.enemies[id] = nil
+end
 function EnemySystem:get(id)
 	return self.enemies[id]
 end
@@ -214,4 +226,4 @@ end)
 
 print("still, ", world:get("Enemy", enemy_id) ~= nil)
 
---world:delete_entity(player_id)
+--world:despawn(player_id)
@@ -1,8 +1,3 @@
-mapdefs = {
-	{Render={sprite="player_main", animations={idle={frame_width=32, current_frame=1}}, animation="idle"}},
-	{Render={sprite="player_main", animations={idle={frame_width=32, current_frame=2}}, animation="idle"}},
-}
-
 lvl0 = {
 	{0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01},
 	{0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01},
@@ -14,19 +9,16 @@ lvl0 = {
 	{0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01, 0x01},
 }
 
---[[
-for i, row in ipairs(lvl0) do
-	for j, t in ipairs(row) do
-		io.write(t)
-		if t ~= 0 then
-			local tile = mapdefs[t]
-			--tile.Render.x = (j - 1) * 32
-			--tile.Render.y = (i - 1) * 32
-			tile.Render.x = j * 32
-			tile.Render.y = i * 32
-			world:spawn(tile)
-		end
-	end
-	print()
-end
---]]
\ No newline at end of file
+--tile n is the nth 32x32 frame of player_main, and both kinds are walls
+map_id = world:spawn({
+	Tilemap = {
+		tileset = "player_main",
+		tile_width = 32,
+		tile_height = 32,
+		--tile (1, 1) is centered on 32, 32
+		x = 16,
+		y = 16,
+		tiles = lvl0,
+		solid = {0x01, 0x02},
+	}
+})
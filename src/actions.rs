@@ -0,0 +1,216 @@
+use serde::{Serialize, Deserialize};
+use crate::input::{InputEvent, InputState};
+
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+
+//something an action can be bound to, written like {"key": "Space"}, {"mouse": "Left"} or {"button": "a"}
+//names are the same ones InputEvent uses
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Binding {
+	Key(String),
+	Mouse(String),
+	//on any controller
+	Button(String),
+}
+
+impl Binding {
+	//the binding an event pushes down, if it's that kind of event
+	fn pressed_by(event: &InputEvent) -> Option<Binding> {
+		match event {
+			InputEvent::KeyDown{key} => Some(Binding::Key(key.clone())),
+			InputEvent::MouseDown{button} => Some(Binding::Mouse(button.clone())),
+			InputEvent::ButtonDown{button, ..} => Some(Binding::Button(button.clone())),
+			_ => None,
+		}
+	}
+	fn is_held(&self, state: &InputState) -> bool {
+		match self {
+			Binding::Key(key) => state.keyboard.contains_key(key),
+			Binding::Mouse(button) => state.mouse.buttons.contains_key(button),
+			Binding::Button(button) => state.controllers.values().any(|c| c.buttons.contains_key(button)),
+		}
+	}
+}
+
+//action name -> everything that triggers it, e.g. resources/bindings.json
+//sorted so the saved file doesn't shuffle around every time it's written
+pub type Bindings = BTreeMap<String, Vec<Binding>>;
+
+//named actions on top of the raw InputState, with edge detection done per tick
+#[derive(Debug)]
+pub struct Actions {
+	pub bindings: Bindings,
+	//where rebinding saves to, None = don't
+	path: Option<PathBuf>,
+	held: HashSet<String>,
+	pressed: HashSet<String>,
+	released: HashSet<String>,
+	//had one of their bindings go down since the last tick; a tap can come and go between two ticks
+	downs: HashSet<String>,
+	//the next thing pressed gets bound to this instead
+	listening: Option<String>,
+}
+
+impl Default for Actions {
+	fn default() -> Actions {
+		Actions::new(Bindings::new(), None)
+	}
+}
+
+impl Actions {
+	pub fn new(bindings: Bindings, path: Option<PathBuf>) -> Actions {
+		Actions{bindings, path, held: HashSet::new(), pressed: HashSet::new(), released: HashSet::new(), downs: HashSet::new(), listening: None}
+	}
+	//actions this event is a press of, for things that can't wait for a tick (like opening the console)
+	pub fn triggered_by(&self, event: &InputEvent) -> Vec<&str> {
+		match Binding::pressed_by(event) {
+			Some(binding) => self.bindings.iter()
+				.filter(|(_, bindings)| bindings.contains(&binding))
+				.map(|(action, _)| action.as_str())
+				.collect(),
+			None => Vec::new(),
+		}
+	}
+	//goes through every event before InputState::apply gets it
+	//returns an error if an event finished a rebind_next but the bindings couldn't be saved
+	pub fn apply(&mut self, event: &InputEvent) -> Result<(), String> {
+		let binding = match Binding::pressed_by(event) {
+			Some(binding) => binding,
+			None => return Ok(()),
+		};
+		//the press that rebinds an action doesn't count as pressing it
+		if let Some(action) = self.listening.take() {
+			return self.bind(&action, vec![binding]);
+		}
+		for (action, bindings) in &self.bindings {
+			if bindings.contains(&binding) {
+				self.downs.insert(action.clone());
+			}
+		}
+		Ok(())
+	}
+	//once a tick, after every event has been applied to state
+	pub fn update(&mut self, state: &InputState) {
+		let held: HashSet<String> = self.bindings.iter()
+			.filter(|(_, bindings)| bindings.iter().any(|b| b.is_held(state)))
+			.map(|(action, _)| action.clone())
+			.collect();
+		self.pressed = self.downs.iter().filter(|a| !self.held.contains(*a)).cloned().collect();
+		self.released = self.held.difference(&held).cloned().collect();
+		//pressed and let go again before this tick: pressed and released both
+		for action in &self.pressed {
+			if !held.contains(action) {
+				self.released.insert(action.clone());
+			}
+		}
+		self.held = held;
+		self.downs.clear();
+	}
+	pub fn held(&self) -> &HashSet<String> {
+		&self.held
+	}
+	pub fn pressed(&self) -> &HashSet<String> {
+		&self.pressed
+	}
+	pub fn released(&self) -> &HashSet<String> {
+		&self.released
+	}
+
+	//replaces everything bound to action and saves
+	pub fn bind(&mut self, action: &str, bindings: Vec<Binding>) -> Result<(), String> {
+		self.bindings.insert(action.to_string(), bindings);
+		self.save()
+	}
+	pub fn rebind_next(&mut self, action: &str) {
+		self.listening = Some(action.to_string());
+	}
+	pub fn is_listening(&self) -> bool {
+		self.listening.is_some()
+	}
+	fn save(&self) -> Result<(), String> {
+		let path = match &self.path {
+			Some(path) => path,
+			None => return Ok(()),
+		};
+		let json = serde_json::to_string_pretty(&self.bindings).map_err(|e| e.to_string())?;
+		std::fs::write(path, json).map_err(|e| format!("can't save bindings to {}: {}", path.display(), e))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn key(name: &str) -> Binding {
+		Binding::Key(name.to_string())
+	}
+
+	//events go through the actions and then the state, like InputSystem::tick does
+	fn tick(actions: &mut Actions, state: &mut InputState, events: &[InputEvent]) -> (bool, bool, bool) {
+		for event in events {
+			actions.apply(event).unwrap();
+			state.apply(event);
+		}
+		actions.update(state);
+		let action = "fire";
+		(actions.pressed().contains(action), actions.held().contains(action), actions.released().contains(action))
+	}
+
+	fn down(key: &str) -> InputEvent {
+		InputEvent::KeyDown{key: key.to_string()}
+	}
+	fn up(key: &str) -> InputEvent {
+		InputEvent::KeyUp{key: key.to_string()}
+	}
+
+	fn actions() -> Actions {
+		let mut bindings = Bindings::new();
+		bindings.insert("fire".to_string(), vec![key("X"), Binding::Mouse("Left".to_string())]);
+		Actions::new(bindings, None)
+	}
+
+	#[test]
+	fn edges_only_last_a_tick() {
+		let (mut actions, mut state) = (actions(), InputState::default());
+		assert_eq!(tick(&mut actions, &mut state, &[down("X")]), (true, true, false));
+		assert_eq!(tick(&mut actions, &mut state, &[]), (false, true, false));
+		assert_eq!(tick(&mut actions, &mut state, &[up("X")]), (false, false, true));
+		assert_eq!(tick(&mut actions, &mut state, &[]), (false, false, false));
+	}
+
+	#[test]
+	fn any_binding_keeps_it_held() {
+		let (mut actions, mut state) = (actions(), InputState::default());
+		tick(&mut actions, &mut state, &[down("X")]);
+		assert_eq!(tick(&mut actions, &mut state, &[InputEvent::MouseDown{button: "Left".to_string()}]), (false, true, false));
+		assert_eq!(tick(&mut actions, &mut state, &[up("X")]), (false, true, false));
+		assert_eq!(tick(&mut actions, &mut state, &[InputEvent::MouseUp{button: "Left".to_string()}]), (false, false, true));
+		//keys that aren't bound to it do nothing
+		assert_eq!(tick(&mut actions, &mut state, &[down("Z"), up("Z")]), (false, false, false));
+	}
+
+	#[test]
+	fn taps_and_retaps_inside_one_tick() {
+		let (mut actions, mut state) = (actions(), InputState::default());
+		assert_eq!(tick(&mut actions, &mut state, &[down("X"), up("X")]), (true, false, true));
+		tick(&mut actions, &mut state, &[down("X")]);
+		//let go and pressed again before the tick, it never stopped being held
+		assert_eq!(tick(&mut actions, &mut state, &[up("X"), down("X")]), (false, true, false));
+	}
+
+	#[test]
+	fn the_press_that_rebinds_doesnt_count() {
+		let (mut actions, mut state) = (actions(), InputState::default());
+		actions.rebind_next("fire");
+		assert!(actions.is_listening());
+		assert_eq!(tick(&mut actions, &mut state, &[down("C")]), (false, true, false));
+		assert!(!actions.is_listening());
+		assert_eq!(actions.bindings["fire"], vec![key("C")]);
+		assert_eq!(tick(&mut actions, &mut state, &[up("C"), down("C")]), (false, true, false));
+		assert_eq!(actions.triggered_by(&down("C")), vec!["fire"]);
+		assert!(actions.triggered_by(&down("X")).is_empty());
+		assert!(actions.triggered_by(&up("C")).is_empty());
+	}
+}
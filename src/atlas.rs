@@ -0,0 +1,210 @@
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture, TextureCreator};
+use sdl2::surface::Surface;
+
+use std::collections::HashMap;
+
+//biggest page we ask for, SdlRenderer goes smaller if the renderer can't take it
+pub const PAGE_SIZE: u32 = 2048;
+//empty pixels between sprites so scaled/rotated draws don't pick up their neighbours
+const PADDING: u32 = 1;
+
+//where a sprite ended up: which page, and where on it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+	pub page: usize,
+	pub rect: Rect,
+}
+
+//shelf packing: tallest first, left to right, and a new shelf (or page) once the current one is full
+//anything that doesn't fit on a page gets a page of its own
+//returns how big each page needs to be, and where every size went (in the order they were given)
+fn pack(sizes: &[(u32, u32)], page_size: u32) -> (Vec<(u32, u32)>, Vec<Sprite>) {
+	let mut order: Vec<usize> = (0..sizes.len()).collect();
+	order.sort_by_key(|&i| (std::cmp::Reverse(sizes[i].1), std::cmp::Reverse(sizes[i].0), i));
+	let mut pages: Vec<(u32, u32)> = Vec::new();
+	let mut placed = vec![Sprite{page: 0, rect: Rect::new(0, 0, 1, 1)}; sizes.len()];
+	//the page being filled, and its last shelf as (y, height, next free x)
+	let mut current: Option<(usize, u32, u32, u32)> = None;
+	for i in order {
+		let (width, height) = sizes[i];
+		if width > page_size || height > page_size {
+			pages.push((width, height));
+			placed[i] = Sprite{page: pages.len() - 1, rect: Rect::new(0, 0, width, height)};
+			continue;
+		}
+		let (page, y, x) = match current {
+			Some((page, y, shelf_height, x)) if x + width <= page_size => {
+				current = Some((page, y, shelf_height, x + width + PADDING));
+				(page, y, x)
+			},
+			Some((page, y, shelf_height, _)) if y + shelf_height + PADDING + height <= page_size => {
+				let y = y + shelf_height + PADDING;
+				current = Some((page, y, height, width + PADDING));
+				(page, y, 0)
+			},
+			_ => {
+				pages.push((0, 0));
+				current = Some((pages.len() - 1, 0, height, width + PADDING));
+				(pages.len() - 1, 0, 0)
+			},
+		};
+		let extent = &mut pages[page];
+		*extent = (extent.0.max(x + width), extent.1.max(y + height));
+		placed[i] = Sprite{page, rect: Rect::new(x as i32, y as i32, width, height)};
+	}
+	(pages, placed)
+}
+
+//every image copied onto its spot on a page, all in software
+fn build_pages(images: &[(String, Surface<'static>)], page_size: u32) -> Result<(Vec<Surface<'static>>, HashMap<String, Sprite>), String> {
+	let sizes: Vec<(u32, u32)> = images.iter().map(|(_, image)| image.size()).collect();
+	let (page_sizes, placed) = pack(&sizes, page_size);
+	let mut pages = Vec::with_capacity(page_sizes.len());
+	for (width, height) in page_sizes {
+		let mut page = Surface::new(width, height, PixelFormatEnum::ARGB8888)?;
+		page.fill_rect(None, Color::RGBA(0, 0, 0, 0))?;
+		pages.push(page);
+	}
+	let mut sprites = HashMap::new();
+	for ((name, image), sprite) in images.iter().zip(placed) {
+		image.blit(None, &mut pages[sprite.page], sprite.rect)?;
+		sprites.insert(name.clone(), sprite);
+	}
+	Ok((pages, sprites))
+}
+
+fn create_textures<T>(texture_creator: &TextureCreator<T>, surfaces: &[Surface]) -> Result<Vec<Texture>, String> {
+	surfaces.iter().map(|s| {
+		let mut texture = texture_creator.create_texture_from_surface(s).map_err(|e| e.to_string())?;
+		texture.set_blend_mode(BlendMode::Blend);
+		Ok(texture)
+	}).collect()
+}
+
+//all the sprites packed into a few big textures, so drawing them doesn't keep switching textures
+pub struct Atlas {
+	pages: Vec<Texture>,
+	sprites: HashMap<String, Sprite>,
+}
+
+impl Atlas {
+	pub fn new() -> Atlas {
+		Atlas{pages: Vec::new(), sprites: HashMap::new()}
+	}
+	pub fn build<T>(texture_creator: &TextureCreator<T>, images: &[(String, Surface<'static>)], page_size: u32) -> Result<Atlas, String> {
+		let (surfaces, sprites) = build_pages(images, page_size)?;
+		Ok(Atlas{pages: create_textures(texture_creator, &surfaces)?, sprites})
+	}
+	pub fn sprite(&self, name: &str) -> Option<Sprite> {
+		self.sprites.get(name).cloned()
+	}
+	//the page a sprite is on, and where on it
+	pub fn get(&self, name: &str) -> Option<(&Texture, Rect)> {
+		self.sprites.get(name).map(|s| (&self.pages[s.page], s.rect))
+	}
+	//for color/alpha mods; they apply to the whole page, so put them back afterwards
+	pub fn get_mut(&mut self, name: &str) -> Option<(&mut Texture, Rect)> {
+		let pages = &mut self.pages;
+		self.sprites.get(name).map(move |s| (&mut pages[s.page], s.rect))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	//every sprite inside its page, the pages no bigger than they need to be, and no two sprites on a page touching
+	fn assert_packed(sizes: &[(u32, u32)], page_size: u32) -> (Vec<(u32, u32)>, Vec<Sprite>) {
+		let (pages, placed) = pack(sizes, page_size);
+		assert_eq!(placed.len(), sizes.len());
+		for (page, &(width, height)) in pages.iter().enumerate() {
+			let on_page: Vec<&Sprite> = placed.iter().filter(|s| s.page == page).collect();
+			assert!(!on_page.is_empty(), "page {} is empty", page);
+			assert_eq!(on_page.iter().map(|s| s.rect.right() as u32).max(), Some(width));
+			assert_eq!(on_page.iter().map(|s| s.rect.bottom() as u32).max(), Some(height));
+		}
+		for (i, (sprite, &(width, height))) in placed.iter().zip(sizes).enumerate() {
+			assert_eq!((sprite.rect.width(), sprite.rect.height()), (width, height));
+			assert!(sprite.rect.x() >= 0 && sprite.rect.y() >= 0);
+			let (page_width, page_height) = pages[sprite.page];
+			assert!(sprite.rect.right() as u32 <= page_width && sprite.rect.bottom() as u32 <= page_height, "{:?} hangs off page {}", sprite, sprite.page);
+			for other in &placed[i + 1..] {
+				//padding included, so they can't even touch
+				let (a, b, padding) = (sprite.rect, other.rect, PADDING as i32);
+				let apart = a.right() + padding <= b.x() || b.right() + padding <= a.x() || a.bottom() + padding <= b.y() || b.bottom() + padding <= a.y();
+				assert!(sprite.page != other.page || apart, "{:?} and {:?} overlap", sprite, other);
+			}
+		}
+		(pages, placed)
+	}
+
+	#[test]
+	fn everything_fits_on_one_page_when_it_can() {
+		let (pages, placed) = assert_packed(&[(10, 10), (20, 5), (5, 20), (30, 30)], 64);
+		assert_eq!(pages.len(), 1);
+		//tallest first
+		assert_eq!(placed[3].rect, Rect::new(0, 0, 30, 30));
+	}
+
+	#[test]
+	fn spills_over_onto_new_shelves_and_pages() {
+		let sizes: Vec<(u32, u32)> = (0..40).map(|i| (5 + i % 7 * 3, 4 + i % 5 * 4)).collect();
+		let (pages, _) = assert_packed(&sizes, 64);
+		assert!(pages.len() > 1);
+		assert!(pages.iter().all(|&(width, height)| width <= 64 && height <= 64));
+	}
+
+	#[test]
+	fn oversized_sprites_get_a_page_of_their_own() {
+		let (pages, placed) = assert_packed(&[(10, 10), (100, 20), (8, 8), (30, 65)], 64);
+		assert_eq!(pages.len(), 3);
+		for &i in &[1, 3] {
+			assert!(placed.iter().enumerate().all(|(j, s)| j == i || s.page != placed[i].page));
+			assert_eq!(pages[placed[i].page], (placed[i].rect.width(), placed[i].rect.height()));
+		}
+		assert_eq!(placed[0].page, placed[2].page);
+	}
+
+	#[test]
+	fn nothing_to_pack() {
+		let (pages, placed) = pack(&[], 64);
+		assert!(pages.is_empty() && placed.is_empty());
+	}
+
+	//an image that's all one color, so wherever it ends up it's easy to tell apart from its neighbours
+	fn image(width: u32, height: u32, color: Color) -> Surface<'static> {
+		let mut image = Surface::new(width, height, PixelFormatEnum::ARGB8888).unwrap();
+		image.fill_rect(None, color).unwrap();
+		image
+	}
+
+	#[test]
+	fn every_sprite_draws_as_its_own_image() {
+		let sizes = [(10, 10), (20, 5), (5, 20), (30, 30), (12, 7), (40, 8), (3, 3), (9, 14)];
+		let color = |i: usize| Color::RGB(40 + i as u8 * 25, 200 - i as u8 * 20, i as u8 * 30);
+		let images: Vec<(String, Surface<'static>)> = sizes.iter().enumerate()
+			.map(|(i, &(width, height))| (i.to_string(), image(width, height, color(i))))
+			.collect();
+		let mut canvas = Surface::new(64, 64, PixelFormatEnum::ARGB8888).unwrap().into_canvas().unwrap();
+		let texture_creator = canvas.texture_creator();
+		//small enough to need a few pages, with one sprite too wide for any of them
+		let atlas = Atlas::build(&texture_creator, &images, 32).unwrap();
+		assert!(atlas.pages.len() > 1);
+		for (i, (name, image)) in images.iter().enumerate() {
+			let (page, rect) = atlas.get(name).unwrap();
+			assert_eq!(rect.size(), image.size());
+			canvas.set_draw_color(Color::RGB(0, 0, 0));
+			canvas.clear();
+			let area = Rect::new(0, 0, rect.width(), rect.height());
+			canvas.copy(page, rect, area).unwrap();
+			let pixels = canvas.read_pixels(area, PixelFormatEnum::ARGB8888).unwrap();
+			let is_color = |p: &[u8]| {
+				let argb = u32::from_ne_bytes([p[0], p[1], p[2], p[3]]);
+				Color::RGBA((argb >> 16) as u8, (argb >> 8) as u8, argb as u8, (argb >> 24) as u8) == color(i)
+			};
+			assert!(pixels.chunks(4).all(is_color), "sprite {} isn't all its own color", name);
+		}
+	}
+}
@@ -0,0 +1,439 @@
+use serde::{Serialize, Deserialize};
+use crate::world::{World, NativeSystem, LuaApi, Field};
+use crate::entity::Entity;
+use crate::error::WorldError;
+use crate::physics::Vector2;
+
+//the logical size SdlRenderer sets up, main sets the real one once there's a window
+pub const SCREEN_WIDTH: u32 = 640;
+pub const SCREEN_HEIGHT: u32 = 400;
+//whatever's being followed, first one the entity has wins
+const FOLLOW_SOURCES: [&str; 2] = ["Transform", "Physics"];
+//anything closer to 0 and to_world divides the screen by nothing
+const MIN_ZOOM: f64 = 0.01;
+
+//x, y is the top left corner
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Area {
+	pub x: f64,
+	pub y: f64,
+	pub width: f64,
+	pub height: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Size {
+	pub width: f64,
+	pub height: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct Shake {
+	//how far it pushes the view, in pixels, at the start
+	pub intensity: f64,
+	pub duration: f64,
+	//seconds left
+	#[serde(default)]
+	pub remaining: f64,
+}
+
+fn one() -> f64 {
+	1.0
+}
+
+//what Lua sees, e.g. Camera = {follow = player_id, smoothing = 4, deadzone = {width = 64, height = 48}}
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Camera {
+	//the world point at the middle of the viewport
+	#[serde(default)]
+	pub position: Vector2,
+	#[serde(default = "one")]
+	pub zoom: f64,
+	//degrees, clockwise
+	#[serde(default)]
+	pub rotation: f64,
+	//screen pixels, None = the whole screen
+	#[serde(default)]
+	pub viewport: Option<Area>,
+	#[serde(default)]
+	pub follow: Option<Entity>,
+	//how quickly it catches up to what it follows, per second; 0 = right away
+	#[serde(default)]
+	pub smoothing: f64,
+	//world pixels around the middle the followed entity can move in without the camera moving
+	#[serde(default)]
+	pub deadzone: Size,
+	//world area the view is kept inside of
+	#[serde(default)]
+	pub bounds: Option<Area>,
+	#[serde(default)]
+	pub shake: Shake,
+}
+
+//a camera as of one frame, for turning world coordinates into screen ones and back
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+	pub x: f64,
+	pub y: f64,
+	pub zoom: f64,
+	pub rotation: f64,
+	pub viewport: Area,
+}
+
+impl Default for View {
+	//no camera: world coordinates are screen coordinates
+	fn default() -> View {
+		View::screen(SCREEN_WIDTH, SCREEN_HEIGHT)
+	}
+}
+
+impl View {
+	pub fn screen(width: u32, height: u32) -> View {
+		let (width, height) = (width as f64, height as f64);
+		View{x: width / 2.0, y: height / 2.0, zoom: 1.0, rotation: 0.0, viewport: Area{x: 0.0, y: 0.0, width, height}}
+	}
+	pub fn to_screen(self, x: f64, y: f64) -> (f64, f64) {
+		let (sin, cos) = (-self.rotation).to_radians().sin_cos();
+		let (dx, dy) = ((x - self.x) * self.zoom, (y - self.y) * self.zoom);
+		(self.viewport.x + self.viewport.width / 2.0 + dx * cos - dy * sin,
+			self.viewport.y + self.viewport.height / 2.0 + dx * sin + dy * cos)
+	}
+	pub fn to_world(self, x: f64, y: f64) -> (f64, f64) {
+		let (sin, cos) = self.rotation.to_radians().sin_cos();
+		let (dx, dy) = ((x - self.viewport.x - self.viewport.width / 2.0) / self.zoom, (y - self.viewport.y - self.viewport.height / 2.0) / self.zoom);
+		(self.x + dx * cos - dy * sin, self.y + dx * sin + dy * cos)
+	}
+}
+
+//moves position just far enough that target is back inside the deadzone around it
+fn deadzone_target(position: Vector2, target: Vector2, deadzone: Size) -> Vector2 {
+	let pull = |position: f64, target: f64, size: f64| {
+		let half = size.max(0.0) / 2.0;
+		if target < position - half {
+			target + half
+		} else if target > position + half {
+			target - half
+		} else {
+			position
+		}
+	};
+	Vector2{x: pull(position.x, target.x, deadzone.width), y: pull(position.y, target.y, deadzone.height)}
+}
+
+//keeps a view of view_size around position inside bounds, or in the middle of them if it's bigger
+fn clamp_to_bounds(position: Vector2, view_size: Size, bounds: Area) -> Vector2 {
+	let clamp = |position: f64, view: f64, start: f64, size: f64| {
+		if view >= size {
+			start + size / 2.0
+		} else {
+			position.max(start + view / 2.0).min(start + size - view / 2.0)
+		}
+	};
+	Vector2{
+		x: clamp(position.x, view_size.width, bounds.x, bounds.width),
+		y: clamp(position.y, view_size.height, bounds.y, bounds.height),
+	}
+}
+
+//cameras, one of which is what the screen gets drawn through (see View)
+//the first camera spawned is the active one until Camera.set_active says otherwise
+//indexed by slot, see World::read_members("Camera")
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CameraSystem {
+	cameras: Vec<Camera>,
+	//slot of the active camera, moved along when a despawn moves it
+	active: Option<usize>,
+	//where each camera was at the start of the current fixed step, like RenderSystem::previous
+	#[serde(skip)]
+	previous: Vec<Vector2>,
+	//seconds, drives the shake
+	#[serde(skip)]
+	time: f64,
+
+	#[serde(skip)]
+	pub screen_width: u32,
+	#[serde(skip)]
+	pub screen_height: u32,
+	//how far the real time is between the last fixed step and the next one, 0..1
+	#[serde(skip)]
+	pub alpha: f64,
+}
+
+impl CameraSystem {
+	fn viewport(&self, camera: &Camera) -> Area {
+		camera.viewport.unwrap_or(Area{x: 0.0, y: 0.0, width: self.screen_width as f64, height: self.screen_height as f64})
+	}
+	//what the active camera sees right now
+	pub fn view(&self) -> View {
+		let i = match self.active {
+			Some(i) => i,
+			None => return View::screen(self.screen_width, self.screen_height),
+		};
+		let camera = &self.cameras[i];
+		let previous = self.previous[i];
+		let mut x = previous.x + (camera.position.x - previous.x) * self.alpha;
+		let mut y = previous.y + (camera.position.y - previous.y) * self.alpha;
+		let Shake{intensity, duration, remaining} = camera.shake;
+		if remaining > 0.0 && duration > 0.0 {
+			//deterministic, so replays come out the same
+			let strength = intensity * remaining / duration;
+			x += (self.time * 97.0).sin() * strength;
+			y += (self.time * 89.0 + 1.3).cos() * strength;
+		}
+		View{x, y, zoom: camera.zoom, rotation: camera.rotation, viewport: self.viewport(camera)}
+	}
+	//zoom gets the same floor as Camera.set_zoom
+	fn parse(value: rlua::Value) -> Result<Camera, WorldError> {
+		let mut camera: Camera = rlua_serde::from_value(value).map_err(WorldError::parse)?;
+		camera.zoom = camera.zoom.max(MIN_ZOOM);
+		Ok(camera)
+	}
+	fn follow_target(world: &World, target: Entity) -> Option<Vector2> {
+		FOLLOW_SOURCES.iter().find_map(|source| match world.read_field(source, target, "position") {
+			Ok(Some(Field::Vector(x, y))) => Some(Vector2{x, y}),
+			_ => None,
+		})
+	}
+}
+
+impl NativeSystem for CameraSystem {
+	fn new() -> CameraSystem {
+		CameraSystem{cameras: Vec::new(), active: None, previous: Vec::new(), time: 0.0, screen_width: SCREEN_WIDTH, screen_height: SCREEN_HEIGHT, alpha: 0.0}
+	}
+	fn lua_api(&self, api: &LuaApi) -> rlua::Result<()> {
+		api.add_function("set_position", |_, c: &mut CameraSystem, members, (id, x, y): (Entity, f64, f64)| {
+			if let Some(i) = members.slot(id) {
+				c.cameras[i].position = Vector2{x, y};
+				//a jump, not something to smooth over
+				c.previous[i] = Vector2{x, y};
+			}
+			Ok(())
+		})?;
+		api.add_function("get_position", |_, c: &mut CameraSystem, members, id: Entity| {
+			Ok(members.slot(id).map(|i| c.cameras[i].position).map_or((None, None), |p| (Some(p.x), Some(p.y))))
+		})?;
+		api.add_function("set_zoom", |_, c: &mut CameraSystem, members, (id, zoom): (Entity, f64)| {
+			if let Some(i) = members.slot(id) {
+				c.cameras[i].zoom = zoom.max(MIN_ZOOM);
+			}
+			Ok(())
+		})?;
+		api.add_function("get_zoom", |_, c: &mut CameraSystem, members, id: Entity| {
+			Ok(members.slot(id).map(|i| c.cameras[i].zoom))
+		})?;
+		api.add_function("set_rotation", |_, c: &mut CameraSystem, members, (id, rotation): (Entity, f64)| {
+			if let Some(i) = members.slot(id) {
+				c.cameras[i].rotation = rotation;
+			}
+			Ok(())
+		})?;
+		//nil to stop following
+		api.add_function("follow", |_, c: &mut CameraSystem, members, (id, target): (Entity, Option<Entity>)| {
+			if let Some(i) = members.slot(id) {
+				c.cameras[i].follow = target;
+			}
+			Ok(())
+		})?;
+		api.add_function("shake", |_, c: &mut CameraSystem, members, (id, intensity, duration): (Entity, f64, f64)| {
+			if let Some(i) = members.slot(id) {
+				c.cameras[i].shake = Shake{intensity, duration, remaining: duration};
+			}
+			Ok(())
+		})?;
+		api.add_function("set_active", |_, c: &mut CameraSystem, members, id: Entity| {
+			if let Some(i) = members.slot(id) {
+				c.active = Some(i);
+			}
+			Ok(())
+		})?;
+		api.add_function("get_active", |_, c: &mut CameraSystem, members, ()| {
+			Ok(c.active.map(|i| members.entities()[i]))
+		})?;
+		//through the active camera, e.g. Camera.screen_to_world(mouse_x, mouse_y)
+		api.add_function("screen_to_world", |_, c: &mut CameraSystem, _, (x, y): (f64, f64)| {
+			Ok(c.view().to_world(x, y))
+		})?;
+		api.add_function("world_to_screen", |_, c: &mut CameraSystem, _, (x, y): (f64, f64)| {
+			Ok(c.view().to_screen(x, y))
+		})?;
+		Ok(())
+	}
+	//reads where followed entities are, so it goes after whatever moves them
+	fn tick(&mut self, world: &World, dt: f64) {
+		self.time += dt;
+		for i in 0..self.cameras.len() {
+			self.previous[i] = self.cameras[i].position;
+			let viewport = self.viewport(&self.cameras[i]);
+			let camera = &mut self.cameras[i];
+			if let Some(target) = camera.follow.and_then(|target| CameraSystem::follow_target(world, target)) {
+				let goal = deadzone_target(camera.position, target, camera.deadzone);
+				let t = if camera.smoothing > 0.0 { 1.0 - (-camera.smoothing * dt).exp() } else { 1.0 };
+				camera.position.x += (goal.x - camera.position.x) * t;
+				camera.position.y += (goal.y - camera.position.y) * t;
+			}
+			if let Some(bounds) = camera.bounds {
+				let view_size = Size{width: viewport.width / camera.zoom, height: viewport.height / camera.zoom};
+				camera.position = clamp_to_bounds(camera.position, view_size, bounds);
+			}
+			camera.shake.remaining = (camera.shake.remaining - dt).max(0.0);
+		}
+	}
+	fn read_field(&self, slot: usize, field: &str) -> Option<Field> {
+		let camera = &self.cameras[slot];
+		match field {
+			"position" => Some(Field::Vector(camera.position.x, camera.position.y)),
+			"zoom" => Some(Field::Number(camera.zoom)),
+			"rotation" => Some(Field::Number(camera.rotation)),
+			_ => None,
+		}
+	}
+	fn spawn<'lua>(&mut self, _: rlua::Context<'lua>, _: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let camera = CameraSystem::parse(object)?;
+		self.previous.push(camera.position);
+		self.cameras.push(camera);
+		if self.active.is_none() {
+			self.active = Some(self.cameras.len() - 1);
+		}
+		Ok(())
+	}
+	fn despawn(&mut self, slot: usize) {
+		self.cameras.swap_remove(slot);
+		self.previous.swap_remove(slot);
+		self.active = match self.active {
+			Some(i) if i == slot => if self.cameras.is_empty() { None } else { Some(0) },
+			//the last camera was moved into the hole
+			Some(i) if i == self.cameras.len() => Some(slot),
+			active => active,
+		};
+	}
+	fn get<'lua>(&self, ctx: rlua::Context<'lua>, i: usize) -> Result<rlua::Value<'lua>, WorldError> {
+		rlua_serde::to_value(ctx, &self.cameras[i]).map_err(WorldError::parse)
+	}
+	fn set<'lua>(&mut self, _: rlua::Context<'lua>, i: usize, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+		self.cameras[i] = CameraSystem::parse(value)?;
+		self.previous[i] = self.cameras[i].position;
+		Ok(())
+	}
+	fn save(&self) -> Result<serde_json::Value, WorldError> {
+		serde_json::to_value(self).map_err(WorldError::parse)
+	}
+	fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError> {
+		let loaded: CameraSystem = serde_json::from_value(state).map_err(WorldError::parse)?;
+		if loaded.active.is_some_and(|i| i >= loaded.cameras.len()) {
+			return Err(WorldError::parse("the active camera isn't one of the cameras"));
+		}
+		self.previous = loaded.cameras.iter().map(|c| c.position).collect();
+		self.cameras = loaded.cameras;
+		self.active = loaded.active;
+		Ok(())
+	}
+	fn slots(&self) -> Result<usize, WorldError> {
+		Ok(self.cameras.len())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn v(x: f64, y: f64) -> Vector2 {
+		Vector2{x, y}
+	}
+
+	fn assert_near((x, y): (f64, f64), (ex, ey): (f64, f64)) {
+		assert!((x - ex).abs() < 1e-9 && (y - ey).abs() < 1e-9, "({}, {}) isn't ({}, {})", x, y, ex, ey);
+	}
+
+	#[test]
+	fn deadzone_only_moves_as_far_as_it_has_to() {
+		let deadzone = Size{width: 20.0, height: 10.0};
+		//inside, nothing moves
+		let p = deadzone_target(v(100.0, 100.0), v(108.0, 96.0), deadzone);
+		assert_near((p.x, p.y), (100.0, 100.0));
+		//outside on both axes, the target ends up right on the edge
+		let p = deadzone_target(v(100.0, 100.0), v(125.0, 80.0), deadzone);
+		assert_near((p.x, p.y), (115.0, 85.0));
+		let p = deadzone_target(v(100.0, 100.0), v(70.0, 106.0), deadzone);
+		assert_near((p.x, p.y), (80.0, 101.0));
+		//no deadzone just follows
+		let p = deadzone_target(v(100.0, 100.0), v(3.0, -4.0), Size::default());
+		assert_near((p.x, p.y), (3.0, -4.0));
+	}
+
+	#[test]
+	fn views_stay_inside_their_bounds() {
+		let bounds = Area{x: 0.0, y: 0.0, width: 1000.0, height: 500.0};
+		let view = Size{width: 200.0, height: 100.0};
+		let p = clamp_to_bounds(v(500.0, 250.0), view, bounds);
+		assert_near((p.x, p.y), (500.0, 250.0));
+		let p = clamp_to_bounds(v(20.0, 480.0), view, bounds);
+		assert_near((p.x, p.y), (100.0, 450.0));
+		let p = clamp_to_bounds(v(-5000.0, -5000.0), view, Area{x: -100.0, y: 50.0, ..bounds});
+		assert_near((p.x, p.y), (0.0, 100.0));
+		//bigger than the bounds gets centered on them, one axis at a time
+		let p = clamp_to_bounds(v(20.0, 480.0), Size{width: 2000.0, height: 100.0}, bounds);
+		assert_near((p.x, p.y), (500.0, 450.0));
+	}
+
+	#[test]
+	fn to_world_undoes_to_screen() {
+		let view = View{x: 120.0, y: -40.0, zoom: 2.5, rotation: 30.0, viewport: Area{x: 10.0, y: 20.0, width: 300.0, height: 200.0}};
+		for &(x, y) in &[(0.0, 0.0), (120.0, -40.0), (-333.0, 17.5), (1e4, -2e3)] {
+			let (sx, sy) = view.to_screen(x, y);
+			assert_near(view.to_world(sx, sy), (x, y));
+		}
+		//the middle of the view is the middle of the viewport
+		assert_near(view.to_screen(120.0, -40.0), (160.0, 120.0));
+		//no camera: nothing changes
+		assert_near(View::screen(640, 400).to_screen(12.0, 34.0), (12.0, 34.0));
+		//the camera turning clockwise turns the world the other way: something to the right of the middle ends up above it
+		let turned = View{rotation: 90.0, zoom: 1.0, ..view};
+		assert_near(turned.to_screen(130.0, -40.0), (160.0, 110.0));
+	}
+
+	#[test]
+	fn the_active_camera_keeps_up_with_despawns() {
+		rlua::Lua::new().context(|ctx| {
+			let mut cameras = CameraSystem::new();
+			for x in 0..3 {
+				let object = ctx.load(&format!("{{position = {{x = {}, y = 0}}}}", x)).eval().unwrap();
+				cameras.spawn(ctx, Entity{index: x, generation: 0}, object).unwrap();
+			}
+			//the first one spawned starts out active
+			assert_eq!(cameras.view().x, 0.0);
+			cameras.active = Some(2);
+			//the last camera gets moved into the hole, and stays active
+			cameras.despawn(0);
+			assert_eq!(cameras.active, Some(0));
+			assert_eq!(cameras.view().x, 2.0);
+			//losing the active one falls back to the first
+			cameras.despawn(0);
+			assert_eq!(cameras.view().x, 1.0);
+			cameras.despawn(0);
+			assert_eq!(cameras.active, None);
+			assert_eq!(cameras.view(), View::screen(SCREEN_WIDTH, SCREEN_HEIGHT));
+		});
+	}
+
+	#[test]
+	fn an_active_camera_that_isnt_there_doesnt_load() {
+		let mut state = CameraSystem::new().save().unwrap();
+		state["active"] = serde_json::json!(0);
+		assert!(CameraSystem::new().load(state).is_err());
+	}
+
+	#[test]
+	fn zoom_never_gets_to_zero() {
+		rlua::Lua::new().context(|ctx| {
+			let mut cameras = CameraSystem::new();
+			cameras.spawn(ctx, Entity{index: 0, generation: 0}, ctx.load("{zoom = 0}").eval().unwrap()).unwrap();
+			assert_eq!(cameras.cameras[0].zoom, MIN_ZOOM);
+			cameras.set(ctx, 0, ctx.load("{zoom = -3}").eval().unwrap()).unwrap();
+			assert_eq!(cameras.cameras[0].zoom, MIN_ZOOM);
+			let (x, y) = cameras.view().to_world(0.0, 0.0);
+			assert!(x.is_finite() && y.is_finite());
+			cameras.set(ctx, 0, ctx.load("{zoom = 2}").eval().unwrap()).unwrap();
+			assert_eq!(cameras.cameras[0].zoom, 2.0);
+		});
+	}
+}
@@ -0,0 +1,688 @@
+use serde::{Serialize, Deserialize};
+use crate::world::{World, NativeSystem, Callback, LuaApi, slot_count};
+use crate::entity::{Entity, Members};
+use crate::error::WorldError;
+use crate::physics::{BodyKind, Material, PhysicsSystem, Vector2};
+use crate::tilemap::TilemapSystem;
+use std::collections::HashMap;
+use rlua::ToLuaMulti;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShapeKind {
+	#[default]
+	Aabb,
+	Circle,
+}
+
+fn yes() -> bool {
+	true
+}
+
+//what Lua sees, e.g. Collision = {shape = "circle", radius = 16, callback = function(self, other) end}
+//shapes are centered on the entity's Physics position
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CollisionObject {
+	#[serde(default)]
+	pub shape: ShapeKind,
+	#[serde(default)]
+	pub width: f64,
+	#[serde(default)]
+	pub height: f64,
+	#[serde(default)]
+	pub radius: f64,
+	//if false this never gets pushed out of solid things, even if its Physics is solid and dynamic
+	#[serde(default = "yes")]
+	pub respond_solid: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Shape {
+	Aabb{half_width: f64, half_height: f64},
+	Circle{radius: f64},
+}
+
+impl Shape {
+	fn from_object(o: &CollisionObject) -> Shape {
+		match o.shape {
+			ShapeKind::Aabb => Shape::Aabb{half_width: o.width / 2.0, half_height: o.height / 2.0},
+			ShapeKind::Circle => Shape::Circle{radius: o.radius},
+		}
+	}
+	fn to_object(self, respond_solid: bool) -> CollisionObject {
+		match self {
+			Shape::Aabb{half_width, half_height} => CollisionObject{shape: ShapeKind::Aabb, width: half_width * 2.0, height: half_height * 2.0, radius: 0.0, respond_solid},
+			Shape::Circle{radius} => CollisionObject{shape: ShapeKind::Circle, width: 0.0, height: 0.0, radius, respond_solid},
+		}
+	}
+	//half extents of the bounding box
+	fn extents(self) -> (f64, f64) {
+		match self {
+			Shape::Aabb{half_width, half_height} => (half_width, half_height),
+			Shape::Circle{radius} => (radius, radius),
+		}
+	}
+}
+
+//normal points from a to b, depth is how far they'd have to move along it to stop overlapping
+struct Contact {
+	normal: Vector2,
+	depth: f64,
+}
+
+fn collide(a: Shape, pa: Vector2, b: Shape, pb: Vector2) -> Option<Contact> {
+	let (dx, dy) = (pb.x - pa.x, pb.y - pa.y);
+	match (a, b) {
+		(Shape::Aabb{half_width: aw, half_height: ah}, Shape::Aabb{half_width: bw, half_height: bh}) => {
+			let overlap_x = aw + bw - dx.abs();
+			let overlap_y = ah + bh - dy.abs();
+			if overlap_x <= 0.0 || overlap_y <= 0.0 {
+				None
+			} else if overlap_x < overlap_y {
+				Some(Contact{normal: Vector2{x: sign(dx), y: 0.0}, depth: overlap_x})
+			} else {
+				Some(Contact{normal: Vector2{x: 0.0, y: sign(dy)}, depth: overlap_y})
+			}
+		},
+		(Shape::Circle{radius: ra}, Shape::Circle{radius: rb}) => {
+			let distance = (dx * dx + dy * dy).sqrt();
+			if distance >= ra + rb {
+				None
+			} else if distance == 0.0 {
+				Some(Contact{normal: Vector2{x: 1.0, y: 0.0}, depth: ra + rb})
+			} else {
+				Some(Contact{normal: Vector2{x: dx / distance, y: dy / distance}, depth: ra + rb - distance})
+			}
+		},
+		(Shape::Aabb{half_width, half_height}, Shape::Circle{radius}) => {
+			let inside = dx.abs() <= half_width && dy.abs() <= half_height;
+			if inside {
+				//push out through whichever side is closest
+				let to_x = half_width - dx.abs();
+				let to_y = half_height - dy.abs();
+				if to_x < to_y {
+					Some(Contact{normal: Vector2{x: sign(dx), y: 0.0}, depth: to_x + radius})
+				} else {
+					Some(Contact{normal: Vector2{x: 0.0, y: sign(dy)}, depth: to_y + radius})
+				}
+			} else {
+				let (cx, cy) = (dx - dx.max(-half_width).min(half_width), dy - dy.max(-half_height).min(half_height));
+				let distance = (cx * cx + cy * cy).sqrt();
+				if distance >= radius {
+					None
+				} else {
+					Some(Contact{normal: Vector2{x: cx / distance, y: cy / distance}, depth: radius - distance})
+				}
+			}
+		},
+		(Shape::Circle{..}, Shape::Aabb{..}) => collide(b, pb, a, pa).map(|c| Contact{normal: Vector2{x: -c.normal.x, y: -c.normal.y}, depth: c.depth}),
+	}
+}
+
+//distance along the ray to where it enters the shape
+//None if it misses, or if the ray starts inside it (so casting from something doesn't hit itself)
+fn ray_hit(shape: Shape, p: Vector2, origin: Vector2, direction: Vector2) -> Option<f64> {
+	let (ox, oy) = (origin.x - p.x, origin.y - p.y);
+	match shape {
+		Shape::Aabb{half_width, half_height} => match ray_span(half_width, half_height, ox, oy, direction) {
+			Some((near, _)) if near >= 0.0 => Some(near),
+			_ => None,
+		},
+		Shape::Circle{radius} => {
+			let b = ox * direction.x + oy * direction.y;
+			let c = ox * ox + oy * oy - radius * radius;
+			let discriminant = b * b - c;
+			if c <= 0.0 || b > 0.0 || discriminant < 0.0 {
+				None
+			} else {
+				Some(-b - discriminant.sqrt())
+			}
+		},
+	}
+}
+
+//distances along the ray to where the line it's on enters and leaves a box, (ox, oy) is the origin relative to the box's center
+fn ray_span(half_width: f64, half_height: f64, ox: f64, oy: f64, direction: Vector2) -> Option<(f64, f64)> {
+	let (mut near, mut far) = (f64::NEG_INFINITY, f64::INFINITY);
+	for &(o, d, half) in &[(ox, direction.x, half_width), (oy, direction.y, half_height)] {
+		if d == 0.0 {
+			if o.abs() > half {
+				return None;
+			}
+		} else {
+			let (t1, t2) = ((-half - o) / d, (half - o) / d);
+			near = near.max(t1.min(t2));
+			far = far.min(t1.max(t2));
+		}
+	}
+	if near > far {
+		None
+	} else {
+		Some((near, far))
+	}
+}
+
+//pixels per second, see restitution in tick
+const BOUNCE_SPEED: f64 = 30.0;
+
+fn sign(n: f64) -> f64 {
+	if n < 0.0 { -1.0 } else { 1.0 }
+}
+
+//what solid tiles are made of, as far as bouncing and sliding go
+const TILE_MATERIAL: Material = Material{mass: 0.0, restitution: 0.0, friction: 0.0, linear_damping: 0.0, angular_damping: 0.0};
+
+//pushes the Physics bodies a and b apart and takes away whatever velocity is closing the gap, weighted by inverse mass
+//b is None for a solid tile, which never moves
+fn respond(ph: &mut PhysicsSystem, (pa, inverse_a): (usize, f64), (pb, inverse_b): (Option<usize>, f64), contact: &Contact) {
+	let total = inverse_a + inverse_b;
+	if total == 0.0 {
+		return;
+	}
+	let n = contact.normal;
+	ph.positions[pa].x -= n.x * contact.depth * inverse_a / total;
+	ph.positions[pa].y -= n.y * contact.depth * inverse_a / total;
+	if let Some(pb) = pb {
+		ph.positions[pb].x += n.x * contact.depth * inverse_b / total;
+		ph.positions[pb].y += n.y * contact.depth * inverse_b / total;
+	}
+
+	//whatever a script set on a static body, it isn't actually moving
+	let velocity = |s: Option<usize>| match s {
+		Some(s) if ph.kinds[s] != BodyKind::Static => ph.velocities[s],
+		_ => Vector2::default(),
+	};
+	let (va, vb) = (velocity(Some(pa)), velocity(pb));
+	let relative = Vector2{x: vb.x - va.x, y: vb.y - va.y};
+	let closing = relative.x * n.x + relative.y * n.y;
+	//already moving apart
+	if closing >= 0.0 {
+		return;
+	}
+	let (ma, mb) = (ph.materials[pa], pb.map_or(TILE_MATERIAL, |pb| ph.materials[pb]));
+	//slow hits don't bounce, otherwise things resting on the ground jitter forever
+	let restitution = if -closing > BOUNCE_SPEED { ma.restitution.max(mb.restitution) } else { 0.0 };
+	let j = -(1.0 + restitution) * closing / total;
+	let mut impulse = Vector2{x: n.x * j, y: n.y * j};
+	//friction works against the sliding part of the relative velocity, up to friction * j
+	let tangent = Vector2{x: relative.x - n.x * closing, y: relative.y - n.y * closing};
+	let sliding = (tangent.x * tangent.x + tangent.y * tangent.y).sqrt();
+	if sliding > 0.0 {
+		let friction = (ma.friction * mb.friction).sqrt();
+		let jt = (sliding / total).min(friction * j);
+		impulse.x -= tangent.x / sliding * jt;
+		impulse.y -= tangent.y / sliding * jt;
+	}
+	ph.velocities[pa].x -= impulse.x * inverse_a;
+	ph.velocities[pa].y -= impulse.y * inverse_a;
+	if let Some(pb) = pb {
+		ph.velocities[pb].x += impulse.x * inverse_b;
+		ph.velocities[pb].y += impulse.y * inverse_b;
+	}
+}
+
+//indexed by slot, see World::read_members("Collision")
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CollisionSystem {
+	shapes: Vec<Shape>,
+	respond_solid: Vec<bool>,
+	//Lua functions can't be saved, so callbacks are gone after a load
+	#[serde(skip)]
+	callbacks: Vec<Option<rlua::RegistryKey>>,
+	//size of the broadphase grid cells, should be around the size of a typical shape
+	pub cell_size: f64,
+	//pairs of (slot, entity) that overlapped during the last tick
+	#[serde(skip)]
+	contacts: Vec<((usize, Entity), (usize, Entity))>,
+	//same, for overlapping a Tilemap's solid tiles; the other side is the Tilemap entity
+	#[serde(skip)]
+	tile_contacts: Vec<((usize, Entity), Entity)>,
+	//grid of the static bodies, along with PhysicsSystem::statics_changed from when it was built
+	#[serde(skip)]
+	statics: Option<(u64, Grid)>,
+	//Physics positions as of the last tick, and a grid of them that's only built when something asks
+	#[serde(skip)]
+	positions: Vec<Option<Vector2>>,
+	#[serde(skip)]
+	index: Option<Grid>,
+}
+
+//the queries only know about entities with a Collision shape, at their positions as of the last tick
+//they return slots, World::members("Collision") turns those into entities
+impl CollisionSystem {
+	fn build_index(&mut self) {
+		if self.index.is_none() {
+			self.index = Some(build_grid(&self.shapes, &self.positions, 0..self.shapes.len(), self.cell_size));
+		}
+	}
+	//everything overlapping a shape centered on p
+	fn query_shape(&mut self, shape: Shape, p: Vector2) -> Vec<usize> {
+		self.build_index();
+		let index = self.index.as_ref().unwrap();
+		let mut slots: Vec<usize> = cells(shape, p, self.cell_size).filter_map(|cell| index.get(&cell)).flatten().cloned().collect();
+		slots.sort_unstable();
+		slots.dedup();
+		slots.retain(|&i| collide(shape, p, self.shapes[i], self.positions[i].unwrap()).is_some());
+		slots
+	}
+	pub fn query_point(&mut self, p: Vector2) -> Vec<usize> {
+		self.query_shape(Shape::Circle{radius: 0.0}, p)
+	}
+	pub fn query_aabb(&mut self, min: Vector2, max: Vector2) -> Vec<usize> {
+		let center = Vector2{x: (min.x + max.x) / 2.0, y: (min.y + max.y) / 2.0};
+		self.query_shape(Shape::Aabb{half_width: (max.x - min.x).abs() / 2.0, half_height: (max.y - min.y).abs() / 2.0}, center)
+	}
+	pub fn query_radius(&mut self, center: Vector2, radius: f64) -> Vec<usize> {
+		self.query_shape(Shape::Circle{radius}, center)
+	}
+	//closest thing the ray enters within max_distance, and how far along it is
+	//walks the grid cell by cell and stops as soon as nothing in a later cell could be closer
+	pub fn raycast(&mut self, origin: Vector2, direction: Vector2, max_distance: f64) -> Option<(usize, f64)> {
+		let length = (direction.x * direction.x + direction.y * direction.y).sqrt();
+		if length == 0.0 {
+			return None;
+		}
+		let direction = Vector2{x: direction.x / length, y: direction.y / length};
+		self.build_index();
+		let index = self.index.as_ref().unwrap();
+		let cell_size = self.cell_size;
+		//only the box around the occupied cells is worth walking through, so the walk starts where the ray enters it
+		//and ends where it leaves, however far away the origin is or however big max_distance is (math.huge included)
+		let mut keys = index.keys();
+		let &(x, y) = keys.next()?;
+		let (min, max) = keys.fold(((x, y), (x, y)), |(min, max), &(x, y)| ((min.0.min(x), min.1.min(y)), (max.0.max(x), max.1.max(y))));
+		let (half_width, half_height) = ((max.0 - min.0 + 1) as f64 * cell_size / 2.0, (max.1 - min.1 + 1) as f64 * cell_size / 2.0);
+		let (ox, oy) = (origin.x - min.0 as f64 * cell_size - half_width, origin.y - min.1 as f64 * cell_size - half_height);
+		let (enter, leave) = ray_span(half_width, half_height, ox, oy, direction)?;
+		let enter = enter.max(0.0);
+		if enter > max_distance || enter > leave {
+			return None;
+		}
+		let max_distance = max_distance.min(leave);
+		let cell = |n: f64| (n / cell_size).floor() as i64;
+		let (mut x, mut y) = (cell(origin.x + direction.x * enter).max(min.0).min(max.0), cell(origin.y + direction.y * enter).max(min.1).min(max.1));
+		//distance to the next cell boundary on each axis, and between boundaries
+		let boundary = |c: i64, o: f64, d: f64| {
+			if d > 0.0 {
+				((c + 1) as f64 * cell_size - o) / d
+			} else if d < 0.0 {
+				(c as f64 * cell_size - o) / d
+			} else {
+				f64::INFINITY
+			}
+		};
+		let (mut next_x, mut next_y) = (boundary(x, origin.x, direction.x), boundary(y, origin.y, direction.y));
+		let (step_x, step_y) = (cell_size / direction.x.abs(), cell_size / direction.y.abs());
+		let mut best: Option<(usize, f64)> = None;
+		loop {
+			for &i in index.get(&(x, y)).map_or(&[][..], |s| &s[..]) {
+				if let Some(distance) = ray_hit(self.shapes[i], self.positions[i].unwrap(), origin, direction) {
+					if distance <= max_distance && best.is_none_or(|(_, d)| distance < d) {
+						best = Some((i, distance));
+					}
+				}
+			}
+			let leave = next_x.min(next_y);
+			if best.is_some_and(|(_, d)| d <= leave) || leave > max_distance {
+				return best;
+			}
+			if next_x < next_y {
+				x += sign(direction.x) as i64;
+				next_x += step_x;
+			} else {
+				y += sign(direction.y) as i64;
+				next_y += step_y;
+			}
+		}
+	}
+
+	//the callback gets pulled out by hand since rlua_serde can't deserialize (or skip) functions
+	fn parse<'lua>(ctx: rlua::Context<'lua>, object: rlua::Value<'lua>) -> Result<(CollisionObject, Option<rlua::RegistryKey>), WorldError> {
+		let table = match object {
+			rlua::Value::Table(table) => table,
+			_ => return Err(WorldError::parse("expected a table")),
+		};
+		let data = ctx.create_table().map_err(WorldError::parse)?;
+		let mut callback = None;
+		for pair in table.pairs::<rlua::Value, rlua::Value>() {
+			match pair.map_err(WorldError::parse)? {
+				(rlua::Value::String(ref key), rlua::Value::Function(f)) if key.as_bytes() == b"callback" => {
+					callback = Some(ctx.create_registry_value(f).map_err(WorldError::parse)?);
+				},
+				(_, rlua::Value::Function(_)) => {},
+				(key, value) => data.set(key, value).map_err(WorldError::parse)?,
+			}
+		}
+		let object = rlua_serde::from_value(rlua::Value::Table(data)).map_err(WorldError::parse)?;
+		Ok((object, callback))
+	}
+}
+
+type Grid = HashMap<(i64, i64), Vec<usize>>;
+
+//grid cells a shape's bounding box touches
+fn cells(shape: Shape, p: Vector2, cell_size: f64) -> impl Iterator<Item = (i64, i64)> {
+	let (w, h) = shape.extents();
+	let cell = |n: f64| (n / cell_size).floor() as i64;
+	let (x0, x1, y0, y1) = (cell(p.x - w), cell(p.x + w), cell(p.y - h), cell(p.y + h));
+	(x0..=x1).flat_map(move |x| (y0..=y1).map(move |y| (x, y)))
+}
+
+fn build_grid<I: Iterator<Item = usize>>(shapes: &[Shape], positions: &[Option<Vector2>], slots: I, cell_size: f64) -> Grid {
+	let mut grid = Grid::new();
+	for i in slots {
+		if let Some(p) = positions[i] {
+			for cell in cells(shapes[i], p, cell_size) {
+				grid.entry(cell).or_default().push(i);
+			}
+		}
+	}
+	grid
+}
+
+//every pair of slots whose bounding boxes share a grid cell, each pair once, in order
+//static bodies only get paired with moving ones, out of a grid that's kept until they change
+fn broadphase(shapes: &[Shape], positions: &[Option<Vector2>], moving: &[usize], statics: &Grid, cell_size: f64) -> Vec<(usize, usize)> {
+	let mut pairs = Vec::new();
+	for slots in build_grid(shapes, positions, moving.iter().cloned(), cell_size).values() {
+		for (n, &a) in slots.iter().enumerate() {
+			for &b in &slots[n + 1..] {
+				pairs.push((a.min(b), a.max(b)));
+			}
+		}
+	}
+	for &a in moving {
+		for cell in cells(shapes[a], positions[a].unwrap(), cell_size) {
+			for &b in statics.get(&cell).map_or(&[][..], |s| &s[..]) {
+				pairs.push((a.min(b), a.max(b)));
+			}
+		}
+	}
+	pairs.sort_unstable();
+	pairs.dedup();
+	pairs
+}
+
+impl NativeSystem for CollisionSystem {
+	fn new() -> CollisionSystem {
+		CollisionSystem{shapes: Vec::new(), respond_solid: Vec::new(), callbacks: Vec::new(), cell_size: 64.0, contacts: Vec::new(), tile_contacts: Vec::new(), statics: None, positions: Vec::new(), index: None}
+	}
+	fn lua_api(&self, api: &LuaApi) -> rlua::Result<()> {
+		let entities = |members: &Members, slots: Vec<usize>| -> Vec<Entity> {
+			slots.into_iter().map(|i| members.entities()[i]).collect()
+		};
+		api.add_function("query_point", move |_, c: &mut CollisionSystem, members, (x, y): (f64, f64)| {
+			Ok(entities(members, c.query_point(Vector2{x, y})))
+		})?;
+		api.add_function("query_aabb", move |_, c: &mut CollisionSystem, members, (x1, y1, x2, y2): (f64, f64, f64, f64)| {
+			Ok(entities(members, c.query_aabb(Vector2{x: x1, y: y1}, Vector2{x: x2, y: y2})))
+		})?;
+		api.add_function("query_radius", move |_, c: &mut CollisionSystem, members, (x, y, radius): (f64, f64, f64)| {
+			Ok(entities(members, c.query_radius(Vector2{x, y}, radius)))
+		})?;
+		//id, hit_x, hit_y, distance or nil if nothing was hit
+		api.add_function("raycast", |_, c: &mut CollisionSystem, members, (x, y, dx, dy, max): (f64, f64, f64, f64, f64)| {
+			Ok(match c.raycast(Vector2{x, y}, Vector2{x: dx, y: dy}, max) {
+				Some((i, distance)) => {
+					let length = (dx * dx + dy * dy).sqrt();
+					(Some(members.entities()[i]), Some(x + dx / length * distance), Some(y + dy / length * distance), Some(distance))
+				},
+				None => (None, None, None, None),
+			})
+		})?;
+		Ok(())
+	}
+	//needs to run after Physics has moved everything, and writes positions/velocities back into it
+	fn tick(&mut self, world: &World, _: f64) {
+		self.contacts.clear();
+		self.tile_contacts.clear();
+		let entities = match world.members("Collision") {
+			Ok(entities) => entities,
+			Err(_) => return,
+		};
+		//solid_tiles says which map by slot, this turns that back into the Tilemap entity
+		let maps = world.members("Tilemap").unwrap_or_default();
+		//Physics slot of every Collision slot, things without Physics can't collide
+		let physics_slots: Vec<Option<usize>> = match world.read_members("Physics", |m| entities.iter().map(|&e| m.slot(e)).collect()) {
+			Ok(slots) => slots,
+			Err(_) => return,
+		};
+		self.index = None;
+		let contacts = &mut self.contacts;
+		let tile_contacts = &mut self.tile_contacts;
+		let statics = &mut self.statics;
+		let cached = &mut self.positions;
+		let shapes = &self.shapes;
+		let respond_solid = &self.respond_solid;
+		let cell_size = self.cell_size;
+		let _ = world.write_native_system("Physics", |ph: &mut PhysicsSystem| {
+			let positions: Vec<Option<Vector2>> = physics_slots.iter().map(|s| s.map(|s| ph.positions[s])).collect();
+			let is_static = |i: usize| physics_slots[i].is_some_and(|s| ph.kinds[s] == BodyKind::Static);
+			if statics.as_ref().is_none_or(|(changed, _)| *changed != ph.statics_changed) {
+				let grid = build_grid(shapes, &positions, (0..shapes.len()).filter(|&i| is_static(i)), cell_size);
+				*statics = Some((ph.statics_changed, grid));
+			}
+			let moving: Vec<usize> = (0..shapes.len()).filter(|&i| positions[i].is_some() && !is_static(i)).collect();
+			let pairs = broadphase(shapes, &positions, &moving, &statics.as_ref().unwrap().1, cell_size);
+			for (a, b) in pairs {
+				let (pa, pb) = (physics_slots[a].unwrap(), physics_slots[b].unwrap());
+				let contact = match collide(shapes[a], ph.positions[pa], shapes[b], ph.positions[pb]) {
+					Some(contact) => contact,
+					None => continue,
+				};
+				contacts.push(((a, entities[a]), (b, entities[b])));
+				if !(ph.solid[pa] && ph.solid[pb]) {
+					continue;
+				}
+				//heavier bodies get pushed less, infinitely heavy ones (and ones that don't respond) not at all
+				let inverse_a = if respond_solid[a] { ph.inverse_mass(pa) } else { 0.0 };
+				let inverse_b = if respond_solid[b] { ph.inverse_mass(pb) } else { 0.0 };
+				respond(ph, (pa, inverse_a), (Some(pb), inverse_b), &contact);
+			}
+			//solid tiles act like static bodies, one at a time so pushing out of one counts for the next
+			let _ = world.read_native_system("Tilemap", |t: &TilemapSystem| {
+				for &a in &moving {
+					let pa = physics_slots[a].unwrap();
+					let (w, h) = shapes[a].extents();
+					let p = ph.positions[pa];
+					let mut touched = Vec::new();
+					for (map, center, half) in t.solid_tiles(Vector2{x: p.x - w, y: p.y - h}, Vector2{x: p.x + w, y: p.y + h}) {
+						let contact = match collide(shapes[a], ph.positions[pa], Shape::Aabb{half_width: half.x, half_height: half.y}, center) {
+							Some(contact) => contact,
+							None => continue,
+						};
+						if !touched.contains(&map) {
+							touched.push(map);
+						}
+						if ph.solid[pa] && respond_solid[a] {
+							let inverse_a = ph.inverse_mass(pa);
+							respond(ph, (pa, inverse_a), (None, 0.0), &contact);
+						}
+					}
+					tile_contacts.extend(touched.into_iter().map(|map| ((a, entities[a]), maps[map])));
+				}
+			});
+			*cached = physics_slots.iter().map(|s| s.map(|s| ph.positions[s])).collect();
+		});
+	}
+	//callback(self, other) for both sides of every overlap, every step they overlap
+	//touching a Tilemap's solid tiles calls callback(self, tilemap), once per step however many tiles it is
+	fn callbacks<'lua>(&mut self, ctx: rlua::Context<'lua>, _: &Members) -> Vec<Callback<'lua>> {
+		let mut calls = Vec::new();
+		for &((a, ea), (b, eb)) in &self.contacts {
+			for &(slot, this, other) in &[(a, ea, eb), (b, eb, ea)] {
+				if let Some(key) = &self.callbacks[slot] {
+					if let (Ok(f), Ok(args)) = (ctx.registry_value::<rlua::Function>(key), (this, other).to_lua_multi(ctx)) {
+						calls.push((f, args));
+					}
+				}
+			}
+		}
+		for &((slot, this), map) in &self.tile_contacts {
+			if let Some(key) = &self.callbacks[slot] {
+				if let (Ok(f), Ok(args)) = (ctx.registry_value::<rlua::Function>(key), (this, map).to_lua_multi(ctx)) {
+					calls.push((f, args));
+				}
+			}
+		}
+		self.contacts.clear();
+		self.tile_contacts.clear();
+		calls
+	}
+	fn spawn<'lua>(&mut self, ctx: rlua::Context<'lua>, _: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let (object, callback) = CollisionSystem::parse(ctx, object)?;
+		self.shapes.push(Shape::from_object(&object));
+		self.respond_solid.push(object.respond_solid);
+		self.callbacks.push(callback);
+		self.positions.push(None);
+		self.statics = None;
+		self.index = None;
+		Ok(())
+	}
+	fn despawn(&mut self, slot: usize) {
+		self.shapes.swap_remove(slot);
+		self.respond_solid.swap_remove(slot);
+		self.callbacks.swap_remove(slot);
+		//slots just moved around, whatever was found last tick is stale now
+		self.positions.swap_remove(slot);
+		self.contacts.clear();
+		self.tile_contacts.clear();
+		self.statics = None;
+		self.index = None;
+	}
+	fn get<'lua>(&self, ctx: rlua::Context<'lua>, i: usize) -> Result<rlua::Value<'lua>, WorldError> {
+		let value = rlua_serde::to_value(ctx, self.shapes[i].to_object(self.respond_solid[i])).map_err(WorldError::parse)?;
+		if let (rlua::Value::Table(table), Some(key)) = (&value, &self.callbacks[i]) {
+			if let Ok(f) = ctx.registry_value::<rlua::Function>(key) {
+				table.set("callback", f).map_err(WorldError::parse)?;
+			}
+		}
+		Ok(value)
+	}
+	fn set<'lua>(&mut self, ctx: rlua::Context<'lua>, i: usize, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let (object, callback) = CollisionSystem::parse(ctx, value)?;
+		self.shapes[i] = Shape::from_object(&object);
+		self.respond_solid[i] = object.respond_solid;
+		self.callbacks[i] = callback;
+		self.statics = None;
+		self.index = None;
+		Ok(())
+	}
+	fn save(&self) -> Result<serde_json::Value, WorldError> {
+		serde_json::to_value(self).map_err(WorldError::parse)
+	}
+	fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError> {
+		*self = serde_json::from_value(state).map_err(WorldError::parse)?;
+		self.callbacks = self.shapes.iter().map(|_| None).collect();
+		self.positions = vec![None; self.shapes.len()];
+		Ok(())
+	}
+	fn slots(&self) -> Result<usize, WorldError> {
+		slot_count(&[("shapes", self.shapes.len()), ("respond_solid", self.respond_solid.len())])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn v(x: f64, y: f64) -> Vector2 {
+		Vector2{x, y}
+	}
+
+	fn aabb(width: f64, height: f64) -> Shape {
+		Shape::Aabb{half_width: width / 2.0, half_height: height / 2.0}
+	}
+
+	fn assert_contact(contact: Option<Contact>, normal: (f64, f64), depth: f64) {
+		let contact = contact.expect("expected an overlap");
+		assert!((contact.normal.x - normal.0).abs() < 1e-9 && (contact.normal.y - normal.1).abs() < 1e-9, "normal was {:?}", contact.normal);
+		assert!((contact.depth - depth).abs() < 1e-9, "depth was {}", contact.depth);
+	}
+
+	#[test]
+	fn aabbs_push_apart_along_the_shallower_axis() {
+		//overlapping 2 on x and 8 on y
+		assert_contact(collide(aabb(10.0, 10.0), v(0.0, 0.0), aabb(10.0, 10.0), v(8.0, 2.0)), (1.0, 0.0), 2.0);
+		assert_contact(collide(aabb(10.0, 10.0), v(0.0, 0.0), aabb(10.0, 10.0), v(-2.0, -7.0)), (0.0, -1.0), 3.0);
+		//just touching isn't overlapping
+		assert!(collide(aabb(10.0, 10.0), v(0.0, 0.0), aabb(10.0, 10.0), v(10.0, 0.0)).is_none());
+		assert!(collide(aabb(10.0, 10.0), v(0.0, 0.0), aabb(4.0, 4.0), v(3.0, 20.0)).is_none());
+	}
+
+	#[test]
+	fn circles_push_apart_along_the_line_between_them() {
+		assert_contact(collide(Shape::Circle{radius: 5.0}, v(0.0, 0.0), Shape::Circle{radius: 6.0}, v(6.0, 8.0)), (0.6, 0.8), 1.0);
+		//just touching isn't overlapping
+		assert!(collide(Shape::Circle{radius: 5.0}, v(0.0, 0.0), Shape::Circle{radius: 5.0}, v(6.0, 8.0)).is_none());
+		assert_contact(collide(Shape::Circle{radius: 5.0}, v(0.0, 0.0), Shape::Circle{radius: 5.0}, v(0.0, -4.0)), (0.0, -1.0), 6.0);
+		//right on top of each other still gets a usable normal
+		assert_contact(collide(Shape::Circle{radius: 2.0}, v(1.0, 1.0), Shape::Circle{radius: 3.0}, v(1.0, 1.0)), (1.0, 0.0), 5.0);
+	}
+
+	#[test]
+	fn aabb_against_circle() {
+		let (square, circle) = (aabb(10.0, 10.0), Shape::Circle{radius: 2.0});
+		//off the side of the box
+		assert_contact(collide(square, v(0.0, 0.0), circle, v(6.0, 1.0)), (1.0, 0.0), 1.0);
+		//off a corner, the normal points from the corner to the center
+		assert_contact(collide(square, v(0.0, 0.0), circle, v(6.0, 6.0)), (0.5f64.sqrt(), 0.5f64.sqrt()), 2.0 - 2.0f64.sqrt());
+		assert!(collide(square, v(0.0, 0.0), circle, v(7.0, 7.0)).is_none());
+		//center inside the box gets pushed out through the closest side
+		assert_contact(collide(square, v(0.0, 0.0), circle, v(1.0, -4.0)), (0.0, -1.0), 3.0);
+		//the other way around flips the normal
+		assert_contact(collide(circle, v(6.0, 1.0), square, v(0.0, 0.0)), (-1.0, 0.0), 1.0);
+		assert_contact(collide(circle, v(1.0, -4.0), square, v(0.0, 0.0)), (0.0, 1.0), 3.0);
+	}
+
+	#[test]
+	fn rays_hit_where_they_enter() {
+		let right = v(1.0, 0.0);
+		assert_eq!(ray_hit(aabb(10.0, 10.0), v(20.0, 0.0), v(0.0, 3.0), right), Some(15.0));
+		assert_eq!(ray_hit(aabb(10.0, 10.0), v(20.0, 0.0), v(0.0, 6.0), right), None);
+		//pointing away
+		assert_eq!(ray_hit(aabb(10.0, 10.0), v(-20.0, 0.0), v(0.0, 0.0), right), None);
+		assert_eq!(ray_hit(Shape::Circle{radius: 5.0}, v(20.0, 0.0), v(0.0, 0.0), right), Some(15.0));
+		assert_eq!(ray_hit(Shape::Circle{radius: 5.0}, v(20.0, 0.0), v(0.0, 5.5), right), None);
+		//starting inside doesn't count
+		assert_eq!(ray_hit(aabb(10.0, 10.0), v(0.0, 0.0), v(1.0, 1.0), right), None);
+		assert_eq!(ray_hit(Shape::Circle{radius: 5.0}, v(0.0, 0.0), v(1.0, 1.0), right), None);
+	}
+
+	#[test]
+	fn broadphase_pairs_show_up_once_however_many_cells_they_share() {
+		//0 and 1 are big enough to share four cells, 2 is far off on its own, 3 is static and overlaps 1
+		let shapes = [aabb(100.0, 100.0), aabb(100.0, 100.0), aabb(10.0, 10.0), aabb(100.0, 100.0)];
+		let positions = [Some(v(0.0, 0.0)), Some(v(10.0, 10.0)), Some(v(1000.0, 1000.0)), Some(v(60.0, 60.0))];
+		let statics = build_grid(&shapes, &positions, 3..4, 64.0);
+		let pairs = broadphase(&shapes, &positions, &[1, 0, 2], &statics, 64.0);
+		assert_eq!(pairs, vec![(0, 1), (0, 3), (1, 3)]);
+		//statics don't get paired with each other
+		let statics = build_grid(&shapes, &positions, 0..4, 64.0);
+		assert!(broadphase(&shapes, &positions, &[], &statics, 64.0).is_empty());
+	}
+
+	fn system(things: &[(Shape, Vector2)]) -> CollisionSystem {
+		let mut c = CollisionSystem::new();
+		for &(shape, p) in things {
+			c.shapes.push(shape);
+			c.respond_solid.push(true);
+			c.callbacks.push(None);
+			c.positions.push(Some(p));
+		}
+		c
+	}
+
+	#[test]
+	fn raycasts_find_the_closest_hit() {
+		let mut c = system(&[(aabb(10.0, 10.0), v(300.0, 0.0)), (Shape::Circle{radius: 5.0}, v(100.0, 0.0)), (aabb(10.0, 10.0), v(0.0, 200.0))]);
+		assert_eq!(c.raycast(v(0.0, 0.0), v(2.0, 0.0), f64::INFINITY), Some((1, 95.0)));
+		assert_eq!(c.raycast(v(0.0, 0.0), v(1.0, 0.0), 50.0), None);
+		assert_eq!(c.raycast(v(0.0, 0.0), v(0.0, 1.0), f64::INFINITY), Some((2, 195.0)));
+		//from far off, outside the grid
+		assert_eq!(c.raycast(v(-1e12, 200.0), v(1.0, 0.0), f64::INFINITY), Some((2, 1e12 - 5.0)));
+		assert_eq!(c.raycast(v(1e12, 1e12), v(1.0, 1.0), f64::INFINITY), None);
+		assert_eq!(c.raycast(v(0.0, 0.0), v(-1.0, 0.0), f64::INFINITY), None);
+		assert_eq!(system(&[]).raycast(v(0.0, 0.0), v(1.0, 0.0), f64::INFINITY), None);
+	}
+}
@@ -0,0 +1,304 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+
+//generational handle: the index gets recycled after a despawn, the generation doesn't,
+//so an old handle to a recycled index can be told apart from the new entity living there
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Entity {
+	pub index: u32,
+	pub generation: u32,
+}
+
+impl Entity {
+	//packed so Lua can keep using ids as plain integers (table keys, printing, etc.)
+	pub fn to_bits(self) -> u64 {
+		(self.generation as u64) << 32 | self.index as u64
+	}
+	pub fn from_bits(bits: u64) -> Entity {
+		Entity{index: bits as u32, generation: (bits >> 32) as u32}
+	}
+}
+
+impl fmt::Display for Entity {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "{}v{}", self.index, self.generation)
+	}
+}
+
+//serialized as the packed integer so it also works as a json map key
+impl Serialize for Entity {
+	fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.serialize_u64(self.to_bits())
+	}
+}
+
+impl<'de> Deserialize<'de> for Entity {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Entity, D::Error> {
+		u64::deserialize(deserializer).map(Entity::from_bits)
+	}
+}
+
+impl<'lua> rlua::ToLua<'lua> for Entity {
+	fn to_lua(self, _: rlua::Context<'lua>) -> rlua::Result<rlua::Value<'lua>> {
+		Ok(rlua::Value::Integer(self.to_bits() as i64))
+	}
+}
+
+impl<'lua> rlua::FromLua<'lua> for Entity {
+	fn from_lua(value: rlua::Value<'lua>, ctx: rlua::Context<'lua>) -> rlua::Result<Entity> {
+		Ok(Entity::from_bits(i64::from_lua(value, ctx)? as u64))
+	}
+}
+
+//a snapshot's allocator gets checked on the way in, see TryFrom<AllocatorState>
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(try_from = "AllocatorState")]
+pub struct EntityAllocator {
+	generations: Vec<u32>,
+	alive: Vec<bool>,
+	free: Vec<u32>,
+}
+
+//an EntityAllocator as it comes out of a snapshot, before anything's been checked
+#[derive(Deserialize)]
+struct AllocatorState {
+	generations: Vec<u32>,
+	alive: Vec<bool>,
+	free: Vec<u32>,
+}
+
+//free has to be exactly the dead indices, otherwise allocate would hand out a live one (or one twice)
+impl TryFrom<AllocatorState> for EntityAllocator {
+	type Error = String;
+	fn try_from(state: AllocatorState) -> Result<EntityAllocator, String> {
+		let AllocatorState{generations, alive, free} = state;
+		if generations.len() != alive.len() {
+			return Err(format!("{} generations for {} entities", generations.len(), alive.len()));
+		}
+		let mut listed = vec![false; alive.len()];
+		for &index in &free {
+			match listed.get_mut(index as usize) {
+				Some(listed) if !*listed && !alive[index as usize] => *listed = true,
+				Some(_) => return Err(format!("entity {} is free more than once, or while alive", index)),
+				None => return Err(format!("free entity {} was never allocated", index)),
+			}
+		}
+		if let Some(index) = (0..alive.len()).find(|&i| !alive[i] && !listed[i]) {
+			return Err(format!("entity {} is dead but not free", index));
+		}
+		Ok(EntityAllocator{generations, alive, free})
+	}
+}
+
+impl EntityAllocator {
+	pub fn new() -> EntityAllocator {
+		EntityAllocator::default()
+	}
+	pub fn allocate(&mut self) -> Entity {
+		if let Some(index) = self.free.pop() {
+			self.alive[index as usize] = true;
+			Entity{index, generation: self.generations[index as usize]}
+		} else {
+			let index = self.generations.len() as u32;
+			self.generations.push(0);
+			self.alive.push(true);
+			Entity{index, generation: 0}
+		}
+	}
+	//returns false if the handle was already stale
+	pub fn free(&mut self, entity: Entity) -> bool {
+		if !self.is_alive(entity) {
+			return false;
+		}
+		let index = entity.index as usize;
+		self.alive[index] = false;
+		//wraps around instead of panicking, a handle would have to survive 2^32 reuses to be confused with a new one
+		self.generations[index] = self.generations[index].wrapping_add(1);
+		self.free.push(entity.index);
+		true
+	}
+	pub fn is_alive(&self, entity: Entity) -> bool {
+		let index = entity.index as usize;
+		index < self.generations.len() && self.alive[index] && self.generations[index] == entity.generation
+	}
+	//number of indices ever handed out, dead or alive
+	pub fn len(&self) -> usize {
+		self.generations.len()
+	}
+	pub fn iter<'a>(&'a self) -> impl Iterator<Item = Entity> + 'a {
+		self.generations.iter().zip(self.alive.iter()).enumerate()
+			.filter(|(_, (_, &alive))| alive)
+			.map(|(index, (&generation, _))| Entity{index: index as u32, generation})
+	}
+}
+
+//sparse set of the entities belonging to one system
+//slot = position in the dense list, systems keep their component data in the same order
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(try_from = "Vec<Entity>", into = "Vec<Entity>")]
+pub struct Members {
+	entities: Vec<Entity>,
+	slots: HashMap<Entity, usize>,
+}
+
+impl Members {
+	pub fn new() -> Members {
+		Members::default()
+	}
+	pub fn insert(&mut self, entity: Entity) -> usize {
+		if let Some(&slot) = self.slots.get(&entity) {
+			return slot;
+		}
+		let slot = self.entities.len();
+		self.entities.push(entity);
+		self.slots.insert(entity, slot);
+		slot
+	}
+	//returns the slot the entity used to occupy; the last member gets moved into it
+	pub fn remove(&mut self, entity: Entity) -> Option<usize> {
+		let slot = self.slots.remove(&entity)?;
+		self.entities.swap_remove(slot);
+		if let Some(&moved) = self.entities.get(slot) {
+			self.slots.insert(moved, slot);
+		}
+		Some(slot)
+	}
+	pub fn slot(&self, entity: Entity) -> Option<usize> {
+		self.slots.get(&entity).cloned()
+	}
+	pub fn contains(&self, entity: Entity) -> bool {
+		self.slots.contains_key(&entity)
+	}
+	pub fn len(&self) -> usize {
+		self.entities.len()
+	}
+	pub fn entities(&self) -> &[Entity] {
+		&self.entities
+	}
+	pub fn iter<'a>(&'a self) -> impl Iterator<Item = Entity> + 'a {
+		self.entities.iter().cloned()
+	}
+}
+
+//an entity in there twice would have two slots, and only one of them could ever be found
+impl TryFrom<Vec<Entity>> for Members {
+	type Error = String;
+	fn try_from(entities: Vec<Entity>) -> Result<Members, String> {
+		let mut slots = HashMap::new();
+		for (slot, &e) in entities.iter().enumerate() {
+			if slots.insert(e, slot).is_some() {
+				return Err(format!("{} is a member twice", e));
+			}
+		}
+		Ok(Members{entities, slots})
+	}
+}
+
+impl From<Members> for Vec<Entity> {
+	fn from(members: Members) -> Vec<Entity> {
+		members.entities
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn despawned_handles_go_stale_and_their_index_gets_reused() {
+		let mut allocator = EntityAllocator::new();
+		let a = allocator.allocate();
+		let b = allocator.allocate();
+		assert!(allocator.free(a));
+		assert!(!allocator.is_alive(a));
+		//freeing twice does nothing
+		assert!(!allocator.free(a));
+		let c = allocator.allocate();
+		assert_eq!(c.index, a.index);
+		assert_ne!(c.generation, a.generation);
+		assert!(allocator.is_alive(c));
+		assert!(!allocator.is_alive(a));
+		assert!(allocator.is_alive(b));
+		assert_eq!(allocator.len(), 2);
+		assert_eq!(allocator.iter().collect::<Vec<_>>(), vec![c, b]);
+	}
+
+	#[test]
+	fn generations_wrap_around() {
+		let mut allocator = EntityAllocator::new();
+		let a = allocator.allocate();
+		allocator.generations[a.index as usize] = u32::MAX;
+		let a = Entity{index: a.index, generation: u32::MAX};
+		assert!(allocator.free(a));
+		let b = allocator.allocate();
+		assert_eq!(b, Entity{index: a.index, generation: 0});
+	}
+
+	#[test]
+	fn bits_round_trip() {
+		let entity = Entity{index: 7, generation: 3};
+		assert_eq!(Entity::from_bits(entity.to_bits()), entity);
+	}
+
+	#[test]
+	fn swap_remove_keeps_slots_and_entities_in_step() {
+		let mut allocator = EntityAllocator::new();
+		let entities: Vec<Entity> = (0..4).map(|_| allocator.allocate()).collect();
+		let mut members = Members::new();
+		for (i, &e) in entities.iter().enumerate() {
+			assert_eq!(members.insert(e), i);
+		}
+		//inserting again keeps the slot it already had
+		assert_eq!(members.insert(entities[2]), 2);
+		//the last member moves into the hole
+		assert_eq!(members.remove(entities[1]), Some(1));
+		assert_eq!(members.entities(), &[entities[0], entities[3], entities[2]]);
+		assert_eq!(members.remove(entities[1]), None);
+		for (slot, &e) in members.entities().iter().enumerate() {
+			assert_eq!(members.slot(e), Some(slot));
+		}
+		//removing the last one doesn't move anything
+		assert_eq!(members.remove(entities[2]), Some(2));
+		assert_eq!(members.slot(entities[3]), Some(1));
+		assert!(!members.contains(entities[2]));
+		assert_eq!(members.len(), 2);
+	}
+
+	#[test]
+	fn members_rebuild_their_slots_when_deserialized() {
+		let members = Members::try_from(vec![Entity{index: 4, generation: 1}, Entity{index: 2, generation: 0}]).unwrap();
+		let json = serde_json::to_value(&members).unwrap();
+		let members: Members = serde_json::from_value(json).unwrap();
+		assert_eq!(members.slot(Entity{index: 2, generation: 0}), Some(1));
+		assert_eq!(members.slot(Entity{index: 4, generation: 0}), None);
+	}
+
+	#[test]
+	fn members_in_there_twice_dont_deserialize() {
+		let json = serde_json::json!([Entity{index: 1, generation: 0}, Entity{index: 2, generation: 0}, Entity{index: 1, generation: 0}]);
+		assert!(serde_json::from_value::<Members>(json).is_err());
+	}
+
+	#[test]
+	fn allocators_that_disagree_with_themselves_dont_deserialize() {
+		let mut allocator = EntityAllocator::new();
+		let a = allocator.allocate();
+		allocator.allocate();
+		allocator.free(a);
+		let json = serde_json::to_value(&allocator).unwrap();
+		assert!(serde_json::from_value::<EntityAllocator>(json.clone()).is_ok());
+		let tampered = |field: &str, value: serde_json::Value| {
+			let mut json = json.clone();
+			json[field] = value;
+			serde_json::from_value::<EntityAllocator>(json).is_err()
+		};
+		assert!(tampered("generations", serde_json::json!([1])));
+		//the live one, one past the end, one twice, and none at all for the dead one
+		assert!(tampered("free", serde_json::json!([1])));
+		assert!(tampered("free", serde_json::json!([2])));
+		assert!(tampered("free", serde_json::json!([0, 0])));
+		assert!(tampered("free", serde_json::json!([])));
+	}
+}
@@ -0,0 +1,76 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::PoisonError;
+
+#[derive(Debug)]
+pub enum WorldError {
+	UnknownSystem(String),
+	//system is left empty by NativeSystems, the World fills it in
+	Parse{system: String, message: String},
+	Lua{system: String, error: rlua::Error},
+	LockPoisoned,
+	//names going around the loop, first one repeated at the end
+	ScheduleCycle(Vec<String>),
+	//tick keeps going when a system fails, so it can have more than one of these
+	Multiple(Vec<WorldError>),
+}
+
+impl WorldError {
+	pub fn parse<E: fmt::Display>(error: E) -> WorldError {
+		WorldError::Parse{system: String::new(), message: error.to_string()}
+	}
+	pub fn lua(system: &str, error: rlua::Error) -> WorldError {
+		WorldError::Lua{system: system.to_string(), error}
+	}
+	pub fn in_system(self, name: &str) -> WorldError {
+		match self {
+			WorldError::Parse{ref system, message} if system.is_empty() => WorldError::Parse{system: name.to_string(), message},
+			e => e,
+		}
+	}
+}
+
+impl fmt::Display for WorldError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			WorldError::UnknownSystem(name) => write!(f, "unknown system '{}'", name),
+			WorldError::Parse{system, message} => write!(f, "could not parse {} component: {}", system, message),
+			WorldError::Lua{system, error} => write!(f, "{}: {}", system, describe_lua_error(error)),
+			WorldError::LockPoisoned => write!(f, "a system lock was poisoned by an earlier panic"),
+			WorldError::ScheduleCycle(names) => write!(f, "system ordering constraints form a cycle: {}", names.join(" -> ")),
+			WorldError::Multiple(errors) => {
+				for (i, e) in errors.iter().enumerate() {
+					if i > 0 {
+						writeln!(f)?;
+					}
+					write!(f, "{}", e)?;
+				}
+				Ok(())
+			},
+		}
+	}
+}
+
+impl Error for WorldError {}
+
+impl<T> From<PoisonError<T>> for WorldError {
+	fn from(_: PoisonError<T>) -> WorldError {
+		WorldError::LockPoisoned
+	}
+}
+
+//lets Lua methods use ? and turns these into errors scripts can pcall
+impl From<WorldError> for rlua::Error {
+	fn from(e: WorldError) -> rlua::Error {
+		rlua::Error::external(e)
+	}
+}
+
+//rlua's Display for CallbackError only shows the traceback, this puts the actual cause in front of it
+pub fn describe_lua_error(error: &rlua::Error) -> String {
+	match error {
+		rlua::Error::CallbackError{traceback, cause} => format!("{}\n{}", describe_lua_error(cause), traceback),
+		rlua::Error::ExternalError(e) => e.to_string(),
+		e => e.to_string(),
+	}
+}
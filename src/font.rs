@@ -0,0 +1,256 @@
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture, TextureCreator};
+use rusttype::{point, Scale};
+
+use std::collections::HashMap;
+
+//a monospaced font laid out as a grid of characters starting at ' ', like resources/font-oldschool
+//only covers ASCII, anything else needs a TTF
+#[derive(Clone, Copy)]
+pub struct BitmapFont<'a> {
+	pub texture: &'a Texture,
+	//where the font is on its atlas page
+	pub rect: Rect,
+	pub char_width: u32,
+	pub char_height: u32,
+}
+
+impl<'a> BitmapFont<'a> {
+	pub fn draw<T: RenderTarget>(&self, canvas: &mut Canvas<T>, string: &str, x: i32, y: i32) {
+		let columns = (self.rect.width() / self.char_width) as i32;
+		let rows = (self.rect.height() / self.char_height) as i32;
+		for (i, c) in string.chars().enumerate() {
+			let position = c as i32 - 32;
+			if position < 0 || position >= columns * rows {
+				continue;
+			}
+			let charx = self.rect.x() + (position % columns) * self.char_width as i32;
+			let chary = self.rect.y() + (position / columns) * self.char_height as i32;
+			let _ = canvas.copy(self.texture,
+				Rect::new(charx, chary, self.char_width, self.char_height),
+				Rect::new(x + i as i32 * self.char_width as i32, y, self.char_width, self.char_height));
+		}
+	}
+}
+
+//which font to draw with: a loaded TTF by that name, or else a bitmap font sprite
+#[derive(Debug, Clone, Copy)]
+pub struct FontStyle<'a> {
+	pub name: &'a str,
+	//pixel height, for TTFs
+	pub size: u32,
+	//cell size, for bitmap fonts
+	pub char_width: u32,
+	pub char_height: u32,
+}
+
+//where a string goes: its top left corner, and the color it's drawn in
+#[derive(Debug, Clone, Copy)]
+pub struct Placement {
+	pub x: i32,
+	pub y: i32,
+	pub color: Color,
+}
+
+const CACHE_SIZE: u32 = 512;
+
+#[derive(Debug, Clone, Copy)]
+struct CachedGlyph {
+	//None if there's nothing to draw, e.g. a space
+	rect: Option<Rect>,
+	//from the pen position on the baseline to the top left of rect
+	left: i32,
+	top: i32,
+	advance: f32,
+}
+
+//TrueType fonts, with every glyph rasterized once (per size) into a cache texture the first time it's drawn
+pub struct Fonts {
+	names: HashMap<String, usize>,
+	fonts: Vec<rusttype::Font<'static>>,
+	texture: Option<Texture>,
+	glyphs: HashMap<(usize, u32, char), CachedGlyph>,
+	//shelf packing again, as (x, y, shelf height); draw starts it over once the texture is full
+	cursor: (u32, u32, u32),
+}
+
+impl Fonts {
+	pub fn new() -> Fonts {
+		Fonts{names: HashMap::new(), fonts: Vec::new(), texture: None, glyphs: HashMap::new(), cursor: (0, 0, 0)}
+	}
+	pub fn load(&mut self, name: &str, bytes: Vec<u8>) -> Result<(), String> {
+		let font = rusttype::Font::from_bytes(bytes).map_err(|e| format!("{}: {}", name, e))?;
+		self.fonts.push(font);
+		self.names.insert(name.to_string(), self.fonts.len() - 1);
+		Ok(())
+	}
+	pub fn contains(&self, name: &str) -> bool {
+		self.names.contains_key(name)
+	}
+	pub fn line_height(&self, name: &str, size: u32) -> Option<u32> {
+		let font = &self.fonts[*self.names.get(name)?];
+		let v = font.v_metrics(Scale::uniform(size as f32));
+		Some((v.ascent - v.descent + v.line_gap).ceil() as u32)
+	}
+	pub fn width(&self, name: &str, size: u32, string: &str) -> Option<u32> {
+		let font = &self.fonts[*self.names.get(name)?];
+		let scale = Scale::uniform(size as f32);
+		let mut previous = None;
+		let mut width = 0.0;
+		for c in string.chars() {
+			let glyph = font.glyph(c);
+			if let Some(previous) = previous {
+				width += font.pair_kerning(scale, previous, glyph.id());
+			}
+			previous = Some(glyph.id());
+			width += glyph.scaled(scale).h_metrics().advance_width;
+		}
+		Some(width.ceil() as u32)
+	}
+
+	//where the next w x h glyph goes, None if the cache is full
+	fn allocate(&mut self, w: u32, h: u32) -> Result<Option<(u32, u32)>, String> {
+		if w > CACHE_SIZE || h > CACHE_SIZE {
+			return Err(format!("a {}x{} glyph doesn't fit in the glyph cache", w, h));
+		}
+		let (mut x, mut y, mut shelf) = self.cursor;
+		if x + w > CACHE_SIZE {
+			x = 0;
+			y += shelf + 1;
+			shelf = 0;
+		}
+		if y + h > CACHE_SIZE {
+			return Ok(None);
+		}
+		self.cursor = (x + w + 1, y, shelf.max(h));
+		Ok(Some((x, y)))
+	}
+	//only safe once whatever was drawn out of the cache is on the canvas, the next glyphs go over the old ones
+	fn clear(&mut self) {
+		self.glyphs.clear();
+		self.cursor = (0, 0, 0);
+	}
+	//None if it isn't cached and there's no room left for it
+	fn glyph<U>(&mut self, texture_creator: &TextureCreator<U>, font: usize, size: u32, c: char) -> Result<Option<CachedGlyph>, String> {
+		if let Some(&cached) = self.glyphs.get(&(font, size, c)) {
+			return Ok(Some(cached));
+		}
+		if self.texture.is_none() {
+			let mut texture = texture_creator.create_texture_streaming(PixelFormatEnum::ARGB8888, CACHE_SIZE, CACHE_SIZE).map_err(|e| e.to_string())?;
+			texture.set_blend_mode(BlendMode::Blend);
+			self.texture = Some(texture);
+		}
+		let glyph = self.fonts[font].glyph(c).scaled(Scale::uniform(size as f32));
+		let advance = glyph.h_metrics().advance_width;
+		let glyph = glyph.positioned(point(0.0, 0.0));
+		let cached = match glyph.pixel_bounding_box() {
+			None => CachedGlyph{rect: None, left: 0, top: 0, advance},
+			Some(bounds) => {
+				let (w, h) = (bounds.width() as u32, bounds.height() as u32);
+				let (x, y) = match self.allocate(w, h)? {
+					Some(position) => position,
+					None => return Ok(None),
+				};
+				//white, with the coverage as alpha; color comes from the color mod when it's drawn
+				let mut pixels = vec![255u8; (w * h * 4) as usize];
+				glyph.draw(|gx, gy, coverage| pixels[((gy * w + gx) * 4 + 3) as usize] = (coverage * 255.0).round() as u8);
+				let rect = Rect::new(x as i32, y as i32, w, h);
+				self.texture.as_mut().unwrap().update(rect, &pixels, (w * 4) as usize).map_err(|e| e.to_string())?;
+				CachedGlyph{rect: Some(rect), left: bounds.min.x, top: bounds.min.y, advance}
+			},
+		};
+		self.glyphs.insert((font, size, c), cached);
+		Ok(Some(cached))
+	}
+	//copies glyphs out of the cache, as (where in the cache, where on the canvas)
+	fn copy_glyphs<T: RenderTarget>(&mut self, canvas: &mut Canvas<T>, color: Color, glyphs: &mut Vec<(Rect, Rect)>) {
+		if let Some(texture) = self.texture.as_mut() {
+			texture.set_color_mod(color.r, color.g, color.b);
+			texture.set_alpha_mod(color.a);
+			for (src, dst) in glyphs.drain(..) {
+				let _ = canvas.copy(texture, src, dst);
+			}
+		}
+	}
+	//false if there's no TTF by the style's name
+	pub fn draw<T: RenderTarget, U>(&mut self, canvas: &mut Canvas<T>, texture_creator: &TextureCreator<U>, style: FontStyle, string: &str, at: Placement) -> Result<bool, String> {
+		let (size, Placement{x, y, color}) = (style.size, at);
+		let font = match self.names.get(style.name) {
+			Some(&font) => font,
+			None => return Ok(false),
+		};
+		//a string with more glyphs than the cache holds gets drawn a cache full at a time
+		let scale = Scale::uniform(size as f32);
+		let baseline = y + self.fonts[font].v_metrics(scale).ascent.round() as i32;
+		let mut pending = Vec::new();
+		let mut pen = 0.0;
+		let mut previous = None;
+		for c in string.chars() {
+			let id = self.fonts[font].glyph(c).id();
+			if let Some(previous) = previous {
+				pen += self.fonts[font].pair_kerning(scale, previous, id);
+			}
+			previous = Some(id);
+			let glyph = match self.glyph(texture_creator, font, size, c)? {
+				Some(glyph) => glyph,
+				None => {
+					self.copy_glyphs(canvas, color, &mut pending);
+					self.clear();
+					self.glyph(texture_creator, font, size, c)?.ok_or_else(|| format!("'{}' doesn't fit in an empty glyph cache", c))?
+				},
+			};
+			if let Some(rect) = glyph.rect {
+				pending.push((rect, Rect::new(x + pen.round() as i32 + glyph.left, baseline + glyph.top, rect.width(), rect.height())));
+			}
+			pen += glyph.advance;
+		}
+		self.copy_glyphs(canvas, color, &mut pending);
+		Ok(true)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use sdl2::surface::Surface;
+
+	const LETTERS: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+	fn fonts() -> Fonts {
+		let mut fonts = Fonts::new();
+		fonts.load("mono", include_bytes!("../resources/DejaVuSansMono.ttf").to_vec()).unwrap();
+		fonts
+	}
+
+	//what a software canvas looks like after draw is done with it
+	fn draw(width: u32, height: u32, f: impl FnOnce(&mut Canvas<Surface<'static>>, &TextureCreator<sdl2::surface::SurfaceContext<'static>>)) -> Vec<u8> {
+		let mut canvas = Surface::new(width, height, PixelFormatEnum::ARGB8888).unwrap().into_canvas().unwrap();
+		let texture_creator = canvas.texture_creator();
+		canvas.set_draw_color(Color::RGB(0, 0, 0));
+		canvas.clear();
+		f(&mut canvas, &texture_creator);
+		canvas.read_pixels(None, PixelFormatEnum::ARGB8888).unwrap()
+	}
+
+	#[test]
+	fn strings_bigger_than_the_cache_come_out_whole() {
+		let style = FontStyle{name: "mono", size: 200, char_width: 0, char_height: 0};
+		let advance = fonts().fonts[0].glyph('A').scaled(Scale::uniform(200.0)).h_metrics().advance_width;
+		let (width, height) = ((advance * LETTERS.len() as f32).ceil() as u32 + 10, fonts().line_height("mono", 200).unwrap());
+		let at = |x: i32| Placement{x, y: 0, color: Color::RGB(255, 255, 255)};
+		//way more letters this big than fit in the cache at once
+		let whole = draw(width, height, |canvas, texture_creator| {
+			assert!(fonts().draw(canvas, texture_creator, style, LETTERS, at(0)).unwrap());
+		});
+		//one letter at a time never fills it, and it's a monospaced font so no kerning either
+		let one_at_a_time = draw(width, height, |canvas, texture_creator| {
+			for (i, c) in LETTERS.chars().enumerate() {
+				let x = (advance * i as f32).round() as i32;
+				assert!(fonts().draw(canvas, texture_creator, style, &c.to_string(), at(x)).unwrap());
+			}
+		});
+		assert!(whole.iter().any(|&p| p != 0));
+		assert!(whole == one_at_a_time, "drawing the whole string doesn't match drawing it a letter at a time");
+	}
+}
@@ -0,0 +1,484 @@
+use serde::{Serialize, Deserialize};
+use crate::world::{World, NativeSystem, LuaApi, Callback};
+use crate::entity::{Entity, Members};
+use crate::error::WorldError;
+use crate::actions::{Actions, Binding};
+use rlua::ToLuaMulti;
+
+use std::collections::{HashMap, VecDeque};
+
+//one thing that happened to an input device, in the form it gets recorded and played back in
+//keys use SDL's key names ("Left", "A", "Space"), controller buttons/axes SDL's controller names ("a", "leftx")
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputEvent {
+	KeyDown{key: String},
+	KeyUp{key: String},
+	MouseMotion{x: i32, y: i32},
+	MouseDown{button: String},
+	MouseUp{button: String},
+	ButtonDown{controller: i32, button: String},
+	ButtonUp{controller: i32, button: String},
+	//-1..1
+	Axis{controller: i32, axis: String, value: f64},
+	ControllerRemoved{controller: i32},
+}
+
+impl InputEvent {
+	//None for anything that isn't input, and for key repeats
+	pub fn from_sdl(event: &sdl2::event::Event) -> Option<InputEvent> {
+		use sdl2::event::Event;
+		match event {
+			Event::KeyDown{keycode: Some(keycode), repeat: false, ..} => Some(InputEvent::KeyDown{key: keycode.name()}),
+			Event::KeyUp{keycode: Some(keycode), ..} => Some(InputEvent::KeyUp{key: keycode.name()}),
+			Event::MouseMotion{x, y, ..} => Some(InputEvent::MouseMotion{x: *x, y: *y}),
+			Event::MouseButtonDown{mouse_btn, ..} => Some(InputEvent::MouseDown{button: format!("{:?}", mouse_btn)}),
+			Event::MouseButtonUp{mouse_btn, ..} => Some(InputEvent::MouseUp{button: format!("{:?}", mouse_btn)}),
+			Event::ControllerButtonDown{which, button, ..} => Some(InputEvent::ButtonDown{controller: *which, button: button.string()}),
+			Event::ControllerButtonUp{which, button, ..} => Some(InputEvent::ButtonUp{controller: *which, button: button.string()}),
+			Event::ControllerAxisMotion{which, axis, value, ..} => Some(InputEvent::Axis{controller: *which, axis: axis.string(), value: (*value as f64 / 32767.0).max(-1.0)}),
+			Event::ControllerDeviceRemoved{which, ..} => Some(InputEvent::ControllerRemoved{controller: *which}),
+			_ => None,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Mouse {
+	pub x: i32,
+	pub y: i32,
+	pub buttons: HashMap<String, bool>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Controller {
+	pub buttons: HashMap<String, bool>,
+	pub axes: HashMap<String, f64>,
+}
+
+//the `input` table callbacks get, e.g. input.keyboard.Left, input.mouse.buttons.Left, input.controllers[0].axes.leftx
+//only what's held down is in there, everything else is nil
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InputState {
+	pub keyboard: HashMap<String, bool>,
+	pub mouse: Mouse,
+	pub controllers: HashMap<i32, Controller>,
+}
+
+impl InputState {
+	pub fn apply(&mut self, event: &InputEvent) {
+		match event {
+			InputEvent::KeyDown{key} => {
+				self.keyboard.insert(key.clone(), true);
+			},
+			InputEvent::KeyUp{key} => {
+				self.keyboard.remove(key);
+			},
+			InputEvent::MouseMotion{x, y} => {
+				self.mouse.x = *x;
+				self.mouse.y = *y;
+			},
+			InputEvent::MouseDown{button} => {
+				self.mouse.buttons.insert(button.clone(), true);
+			},
+			InputEvent::MouseUp{button} => {
+				self.mouse.buttons.remove(button);
+			},
+			InputEvent::ButtonDown{controller, button} => {
+				self.controllers.entry(*controller).or_default().buttons.insert(button.clone(), true);
+			},
+			InputEvent::ButtonUp{controller, button} => {
+				self.controllers.entry(*controller).or_default().buttons.remove(button);
+			},
+			InputEvent::Axis{controller, axis, value} => {
+				self.controllers.entry(*controller).or_default().axes.insert(axis.clone(), *value);
+			},
+			InputEvent::ControllerRemoved{controller} => {
+				self.controllers.remove(controller);
+			},
+		}
+	}
+}
+
+//every event along with the tick it was applied on, counting from when the recording started
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Recording {
+	pub ticks: u64,
+	pub events: Vec<(u64, InputEvent)>,
+}
+
+//keeps the state of every input device up to date from the events it's given
+//and calls callback(self, input) for each of its members every tick
+//input.pressed("jump"), input.held(...) and input.released(...) go through the action bindings, see Actions
+//events either come in live through push, or from a Recording, so a session can be replayed without a window
+//callbacks aren't saved, after a load every member is without one until a script sets it again
+//indexed by slot, see World::read_members("Input")
+#[derive(Debug, Serialize, Deserialize)]
+pub struct InputSystem {
+	//Lua functions can't be saved, so callbacks are gone after a load; a save only has a null per slot
+	#[serde(with = "null_per_slot")]
+	callbacks: Vec<Option<rlua::RegistryKey>>,
+
+	#[serde(skip)]
+	pub state: InputState,
+	//config, not part of the world, so it isn't saved with it either
+	#[serde(skip)]
+	pub actions: Actions,
+	//pushed since the last tick
+	#[serde(skip)]
+	pending: Vec<InputEvent>,
+	#[serde(skip)]
+	tick: u64,
+	//the tick it started on, and what's been recorded so far
+	#[serde(skip)]
+	recording: Option<(u64, Recording)>,
+	//same, but what's left to play
+	#[serde(skip)]
+	playback: Option<(u64, VecDeque<(u64, InputEvent)>)>,
+	//bindings that couldn't be saved during a tick, for the console to pick up
+	#[serde(skip)]
+	errors: Vec<String>,
+}
+
+impl InputSystem {
+	//ignored while a recording is playing
+	pub fn push(&mut self, event: InputEvent) {
+		if self.playback.is_none() {
+			self.pending.push(event);
+		}
+	}
+	pub fn start_recording(&mut self) {
+		self.recording = Some((self.tick, Recording::default()));
+	}
+	pub fn stop_recording(&mut self) -> Option<Recording> {
+		let tick = self.tick;
+		self.recording.take().map(|(start, mut recording)| {
+			recording.ticks = tick - start;
+			recording
+		})
+	}
+	//replaces live input until every event in it has been applied
+	pub fn play(&mut self, recording: Recording) {
+		self.pending.clear();
+		self.playback = Some((self.tick, recording.events.into_iter().collect()));
+	}
+	pub fn take_errors(&mut self) -> Vec<String> {
+		std::mem::take(&mut self.errors)
+	}
+
+	//the state, plus pressed/held/released functions that look up what the actions were this tick
+	fn input_table<'lua>(&self, ctx: rlua::Context<'lua>) -> rlua::Result<rlua::Table<'lua>> {
+		let table = match rlua_serde::to_value(ctx, &self.state)? {
+			rlua::Value::Table(table) => table,
+			_ => ctx.create_table()?,
+		};
+		for (name, actions) in &[("pressed", self.actions.pressed()), ("held", self.actions.held()), ("released", self.actions.released())] {
+			let actions = (*actions).clone();
+			table.set(*name, ctx.create_function(move |_, action: String| Ok(actions.contains(&action)))?)?;
+		}
+		Ok(table)
+	}
+
+	//the callback gets pulled out by hand since rlua_serde can't deserialize (or skip) functions
+	fn parse<'lua>(ctx: rlua::Context<'lua>, object: rlua::Value<'lua>) -> Result<Option<rlua::RegistryKey>, WorldError> {
+		match object {
+			rlua::Value::Table(table) => match table.get::<_, rlua::Value>("callback").map_err(WorldError::parse)? {
+				rlua::Value::Function(f) => Ok(Some(ctx.create_registry_value(f).map_err(WorldError::parse)?)),
+				rlua::Value::Nil => Ok(None),
+				_ => Err(WorldError::parse("callback should be a function")),
+			},
+			rlua::Value::Nil => Ok(None),
+			_ => Err(WorldError::parse("expected a table")),
+		}
+	}
+}
+
+impl NativeSystem for InputSystem {
+	fn new() -> InputSystem {
+		InputSystem{callbacks: Vec::new(), state: InputState::default(), actions: Actions::default(), pending: Vec::new(), tick: 0, recording: None, playback: None, errors: Vec::new()}
+	}
+	fn lua_api(&self, api: &LuaApi) -> rlua::Result<()> {
+		//for systems that would rather look than be called
+		api.add_function("get_state", |ctx, i: &mut InputSystem, _, ()| {
+			rlua_serde::to_value(ctx, &i.state)
+		})?;
+		api.add_function("is_down", |_, i: &mut InputSystem, _, key: String| {
+			Ok(i.state.keyboard.contains_key(&key))
+		})?;
+		api.add_function("pressed", |_, i: &mut InputSystem, _, action: String| {
+			Ok(i.actions.pressed().contains(&action))
+		})?;
+		api.add_function("held", |_, i: &mut InputSystem, _, action: String| {
+			Ok(i.actions.held().contains(&action))
+		})?;
+		api.add_function("released", |_, i: &mut InputSystem, _, action: String| {
+			Ok(i.actions.released().contains(&action))
+		})?;
+		//e.g. Input.bind("jump", {{key = "Space"}, {button = "a"}}), saved right away
+		api.add_function("bind", |_, i: &mut InputSystem, _, (action, bindings): (String, rlua::Value)| {
+			let bindings: Vec<Binding> = rlua_serde::from_value(bindings)?;
+			i.actions.bind(&action, bindings).map_err(rlua::Error::external)?;
+			Ok(())
+		})?;
+		api.add_function("get_bindings", |ctx, i: &mut InputSystem, _, action: Option<String>| {
+			match action {
+				Some(action) => rlua_serde::to_value(ctx, i.actions.bindings.get(&action)),
+				None => rlua_serde::to_value(ctx, &i.actions.bindings),
+			}
+		})?;
+		//the next key/mouse button/controller button pressed replaces whatever action was bound to
+		api.add_function("rebind_next", |_, i: &mut InputSystem, _, action: String| {
+			i.actions.rebind_next(&action);
+			Ok(())
+		})?;
+		api.add_function("is_rebinding", |_, i: &mut InputSystem, _, ()| {
+			Ok(i.actions.is_listening())
+		})?;
+		Ok(())
+	}
+	fn tick(&mut self, _: &World, _: f64) {
+		let now = self.tick;
+		let events: Vec<InputEvent> = match &mut self.playback {
+			Some((start, queue)) => {
+				let mut events = Vec::new();
+				while queue.front().is_some_and(|(tick, _)| *start + tick <= now) {
+					events.push(queue.pop_front().unwrap().1);
+				}
+				events
+			},
+			None => self.pending.drain(..).collect(),
+		};
+		if self.playback.as_ref().is_some_and(|(_, queue)| queue.is_empty()) {
+			self.playback = None;
+		}
+		for event in events {
+			if let Err(e) = self.actions.apply(&event) {
+				self.errors.push(e);
+			}
+			self.state.apply(&event);
+			if let Some((start, recording)) = &mut self.recording {
+				recording.events.push((self.tick - *start, event));
+			}
+		}
+		self.actions.update(&self.state);
+		self.tick += 1;
+	}
+	fn callbacks<'lua>(&mut self, ctx: rlua::Context<'lua>, members: &Members) -> Vec<Callback<'lua>> {
+		let input = match self.input_table(ctx) {
+			Ok(input) => input,
+			Err(_) => return Vec::new(),
+		};
+		let mut calls = Vec::new();
+		for (entity, key) in members.iter().zip(&self.callbacks) {
+			if let Some(key) = key {
+				if let (Ok(f), Ok(args)) = (ctx.registry_value::<rlua::Function>(key), (entity, input.clone()).to_lua_multi(ctx)) {
+					calls.push((f, args));
+				}
+			}
+		}
+		calls
+	}
+	fn spawn<'lua>(&mut self, ctx: rlua::Context<'lua>, _: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let callback = InputSystem::parse(ctx, object)?;
+		self.callbacks.push(callback);
+		Ok(())
+	}
+	fn despawn(&mut self, slot: usize) {
+		self.callbacks.swap_remove(slot);
+	}
+	fn get<'lua>(&self, ctx: rlua::Context<'lua>, i: usize) -> Result<rlua::Value<'lua>, WorldError> {
+		let table = ctx.create_table().map_err(WorldError::parse)?;
+		if let Some(f) = self.callbacks[i].as_ref().and_then(|key| ctx.registry_value::<rlua::Function>(key).ok()) {
+			table.set("callback", f).map_err(WorldError::parse)?;
+		}
+		Ok(rlua::Value::Table(table))
+	}
+	fn set<'lua>(&mut self, ctx: rlua::Context<'lua>, i: usize, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+		self.callbacks[i] = InputSystem::parse(ctx, value)?;
+		Ok(())
+	}
+	fn save(&self) -> Result<serde_json::Value, WorldError> {
+		serde_json::to_value(self).map_err(WorldError::parse)
+	}
+	fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError> {
+		//only the slots come from the save, what's held down right now stays the same
+		//and the callbacks are dropped, the save has no way of saying which function each one was
+		let loaded: InputSystem = serde_json::from_value(state).map_err(WorldError::parse)?;
+		self.callbacks = loaded.callbacks;
+		Ok(())
+	}
+	fn slots(&self) -> Result<usize, WorldError> {
+		Ok(self.callbacks.len())
+	}
+}
+
+//callbacks go into a save as [null, null, ...], which is enough to know how many slots there are
+mod null_per_slot {
+	use serde::{Deserialize, Deserializer, Serializer};
+
+	pub fn serialize<S: Serializer>(callbacks: &[Option<rlua::RegistryKey>], serializer: S) -> Result<S::Ok, S::Error> {
+		serializer.collect_seq(callbacks.iter().map(|_| ()))
+	}
+	pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Option<rlua::RegistryKey>>, D::Error> {
+		Ok(Vec::<()>::deserialize(deserializer)?.into_iter().map(|_| None).collect())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::actions::Bindings;
+	use rlua::FromLuaMulti;
+
+	fn key_down(key: &str) -> InputEvent {
+		InputEvent::KeyDown{key: key.to_string()}
+	}
+	fn key_up(key: &str) -> InputEvent {
+		InputEvent::KeyUp{key: key.to_string()}
+	}
+
+	fn input() -> InputSystem {
+		let mut input = InputSystem::new();
+		let mut bindings = Bindings::new();
+		bindings.insert("jump".to_string(), vec![Binding::Key("Space".to_string()), Binding::Button("a".to_string())]);
+		bindings.insert("left".to_string(), vec![Binding::Key("Left".to_string())]);
+		input.actions = Actions::new(bindings, None);
+		input
+	}
+
+	//which of pressed/held/released the action is in
+	fn edges(input: &InputSystem, action: &str) -> (bool, bool, bool) {
+		let has = |actions: &std::collections::HashSet<String>| actions.contains(action);
+		(has(input.actions.pressed()), has(input.actions.held()), has(input.actions.released()))
+	}
+
+	//what a script would see each tick
+	fn snapshot(input: &InputSystem) -> serde_json::Value {
+		let sorted = |actions: &std::collections::HashSet<String>| {
+			let mut actions: Vec<String> = actions.iter().cloned().collect();
+			actions.sort();
+			actions
+		};
+		serde_json::json!({
+			"state": serde_json::to_value(&input.state).unwrap(),
+			"pressed": sorted(input.actions.pressed()),
+			"held": sorted(input.actions.held()),
+			"released": sorted(input.actions.released()),
+		})
+	}
+
+	#[test]
+	fn pressed_held_released_over_a_few_ticks() {
+		let world = World::new();
+		let mut input = input();
+		input.push(key_down("Space"));
+		input.tick(&world, 0.0);
+		assert_eq!(edges(&input, "jump"), (true, true, false));
+		assert_eq!(input.state.keyboard.get("Space"), Some(&true));
+		input.tick(&world, 0.0);
+		assert_eq!(edges(&input, "jump"), (false, true, false));
+		//the other binding going down too doesn't press it again
+		input.push(InputEvent::ButtonDown{controller: 0, button: "a".to_string()});
+		input.push(key_up("Space"));
+		input.tick(&world, 0.0);
+		assert_eq!(edges(&input, "jump"), (false, true, false));
+		input.push(InputEvent::ButtonUp{controller: 0, button: "a".to_string()});
+		input.tick(&world, 0.0);
+		assert_eq!(edges(&input, "jump"), (false, false, true));
+		assert!(input.state.keyboard.is_empty());
+		input.tick(&world, 0.0);
+		assert_eq!(edges(&input, "jump"), (false, false, false));
+		assert_eq!(edges(&input, "left"), (false, false, false));
+	}
+
+	#[test]
+	fn a_tap_between_two_ticks_is_pressed_and_released_at_once() {
+		let world = World::new();
+		let mut input = input();
+		input.push(key_down("Left"));
+		input.push(key_up("Left"));
+		input.tick(&world, 0.0);
+		assert_eq!(edges(&input, "left"), (true, false, true));
+		input.tick(&world, 0.0);
+		assert_eq!(edges(&input, "left"), (false, false, false));
+	}
+
+	#[test]
+	fn playing_a_recording_gives_the_same_ticks_back() {
+		let world = World::new();
+		let mut live = input();
+		//a tick before the recording starts, so its ticks don't line up with the player's
+		live.tick(&world, 0.0);
+		live.start_recording();
+		let script: Vec<Vec<InputEvent>> = vec![
+			vec![key_down("Space")],
+			vec![],
+			vec![InputEvent::MouseMotion{x: 10, y: 20}, key_down("Left")],
+			vec![key_up("Space"), InputEvent::Axis{controller: 1, axis: "leftx".to_string(), value: -0.5}],
+			vec![key_up("Left"), key_down("Left"), key_up("Left")],
+			vec![],
+			vec![InputEvent::ControllerRemoved{controller: 1}],
+		];
+		let mut expected = Vec::new();
+		for events in &script {
+			for event in events {
+				live.push(event.clone());
+			}
+			live.tick(&world, 0.0);
+			expected.push(snapshot(&live));
+		}
+		let recording = live.stop_recording().unwrap();
+		assert_eq!(recording.ticks, script.len() as u64);
+
+		let mut replay = input();
+		replay.play(recording);
+		for (tick, expected) in expected.iter().enumerate() {
+			//live input doesn't get in while it's playing
+			replay.push(key_down("Right"));
+			replay.tick(&world, 0.0);
+			assert_eq!(&snapshot(&replay), expected, "tick {}", tick);
+		}
+		//and it's back to live input once it's done
+		replay.push(key_down("Right"));
+		replay.tick(&world, 0.0);
+		assert_eq!(replay.state.keyboard.get("Right"), Some(&true));
+	}
+
+	#[test]
+	fn bindings_that_cant_be_saved_wait_for_the_console() {
+		let world = World::new();
+		let mut input = InputSystem::new();
+		input.actions = Actions::new(Bindings::new(), Some("/nonexistent/bindings.json".into()));
+		input.actions.rebind_next("jump");
+		input.push(key_down("Space"));
+		input.tick(&world, 0.0);
+		assert_eq!(input.take_errors().len(), 1);
+		assert!(input.take_errors().is_empty());
+		//still bound, just not saved
+		assert_eq!(input.actions.bindings["jump"], vec![Binding::Key("Space".to_string())]);
+	}
+
+	#[test]
+	fn callbacks_get_whichever_entity_the_world_has_in_their_slot() {
+		rlua::Lua::new().context(|ctx| {
+			let mut input = InputSystem::new();
+			let mut members = Members::new();
+			for index in 0..3 {
+				let entity = Entity{index, generation: 1};
+				members.insert(entity);
+				input.spawn(ctx, entity, ctx.load("{callback = function(id, input) end}").eval().unwrap()).unwrap();
+			}
+			let gone = members.entities()[0];
+			input.despawn(members.remove(gone).unwrap());
+			let ids: Vec<Entity> = input.callbacks(ctx, &members).into_iter()
+				.map(|(_, args)| Entity::from_lua_multi(args, ctx).unwrap())
+				.collect();
+			assert_eq!(ids, members.entities());
+			//a load has no callbacks, but still one slot for each member
+			let mut loaded = InputSystem::new();
+			loaded.load(input.save().unwrap()).unwrap();
+			assert_eq!(loaded.callbacks.len(), 2);
+			assert!(loaded.callbacks(ctx, &members).is_empty());
+		});
+	}
+}
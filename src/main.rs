@@ -6,13 +6,29 @@ extern crate rlua_serde;
 extern crate rust_embed;
 
 extern crate sdl2;
+extern crate image;
+extern crate rusttype;
 use std::error::Error;
 use std::path::Path;
+use std::collections::HashMap;
 
+mod entity;
+mod error;
 mod world;
+mod schedule;
+use schedule::{Stage, SystemOptions};
 use world::NativeSystem;
 mod physics;
+mod collision;
+mod transform;
+mod camera;
+mod tilemap;
+mod input;
+mod actions;
 mod render;
+mod text;
+mod atlas;
+mod font;
 mod sdl_renderer;
 use sdl_renderer::Render;
 mod terminal;
@@ -27,30 +43,89 @@ struct Resources;
 struct Scripts;
 
 
-use std::sync::Arc;
-fn main() ->  Result<(), Box<Error>> {
+//game logic always steps by this much, however fast frames get drawn
+const TIMESTEP: f64 = 1.0 / 60.0;
+//after a long hitch, drop the time instead of trying to catch up on all of it at once
+const MAX_FRAME_TIME: f64 = 0.25;
+//rebound actions get saved here, on top of resources/bindings.json
+const BINDINGS_FILE: &str = "bindings.json";
 
-	let sdl_context = sdl2::init()?;
-	let mut sdl_renderer = sdl_renderer::SdlRenderer::new(&sdl_context, "luasys", 640, 400)?;
+use std::sync::Arc;
+use std::time::Instant;
+fn main() ->  Result<(), Box<dyn Error>> {
 
-	for r in Resources::iter() {
+	let texture_options: HashMap<String, sdl_renderer::TextureOptions> = match Resources::get("textures.json") {
+		Some(json) => serde_json::from_slice(&json)?,
+		None => HashMap::new(),
+	};
+	let mut images = Vec::new();
+	let mut fonts = Vec::new();
+	//shown in the console once there is one, a broken image shouldn't stop the game
+	let mut load_errors = Vec::new();
+	for r in Resources::iter().filter(|r| !r.ends_with(".json")) {
 		//I hate the Path/OsStr APIs
 		let string = r.to_string();
 		let path = Path::new(&string);
 		let file_stem = path.file_stem().unwrap().to_str().unwrap();
-		sdl_renderer.insert_texture(file_stem.to_string(), &Resources::get(&r).unwrap()); 
+		if let Some("ttf") | Some("otf") = path.extension().and_then(|e| e.to_str()) {
+			fonts.push((file_stem.to_string(), Resources::get(&r).unwrap().into_owned()));
+			continue;
+		}
+		let options = texture_options.get(file_stem).cloned().unwrap_or_default();
+		match sdl_renderer::load_image(&string, &Resources::get(&r).unwrap(), &options) {
+			Ok(image) => images.push((file_stem.to_string(), image)),
+			Err(e) => load_errors.push(e),
+		}
 	}
 
-	let mut term = Terminal::new(sdl_renderer.screen_height, 7, 9);
+	//luasys --record <file> or --replay <file>, see below
+	let args: Vec<String> = std::env::args().collect();
+	let mut bindings: actions::Bindings = match Resources::get("bindings.json") {
+		Some(json) => serde_json::from_slice(&json)?,
+		None => actions::Bindings::new(),
+	};
+	//actions that were never rebound keep their defaults, and new ones show up
+	if let Ok(json) = std::fs::read_to_string(BINDINGS_FILE) {
+		match serde_json::from_str::<actions::Bindings>(&json) {
+			Ok(saved) => bindings.extend(saved),
+			Err(e) => load_errors.push(format!("{}: {}", BINDINGS_FILE, e)),
+		}
+	}
+	//a replay shouldn't go rewriting the player's bindings
+	let bindings_path = match args.get(1).map(String::as_str) {
+		Some("--replay") => None,
+		_ => Some(BINDINGS_FILE.into()),
+	};
 
 	let lua = rlua::Lua::new();
 	let world = world::World::new();
 
 	//start out with NativeSystems
-	lua.context(|ctx| {
-		world.add_native_system(ctx, Box::new(physics::PhysicsSystem::new()), "PhysicsSystem", "Physics");
-		world.add_native_system(ctx, Box::new(render::RenderSystem::new()), "RenderSystem", "Render");
-	});
+	lua.context(|ctx| -> Result<(), error::WorldError> {
+		let mut input = input::InputSystem::new();
+		input.actions = actions::Actions::new(bindings, bindings_path);
+		world.add_native_system(ctx, Box::new(input), "Input", SystemOptions::new().stage(Stage::PreUpdate))?;
+		world.add_native_system(ctx, Box::new(physics::PhysicsSystem::new()), "Physics", SystemOptions::new().parallel())?;
+		world.add_native_system(ctx, Box::new(tilemap::TilemapSystem::new()), "Tilemap", SystemOptions::new().parallel())?;
+		world.add_native_system(ctx, Box::new(collision::CollisionSystem::new()), "Collision", SystemOptions::new().after("Physics").writes("Physics").reads("Tilemap"))?;
+		world.add_native_system(ctx, Box::new(transform::TransformSystem::new()), "Transform", SystemOptions::new().stage(Stage::PostUpdate).writes("Physics"))?;
+		//after Transform, so it follows wherever things ended up
+		let camera_options = SystemOptions::new().stage(Stage::RenderPrep).reads("Transform").reads("Physics");
+		world.add_native_system(ctx, Box::new(camera::CameraSystem::new()), "Camera", camera_options)?;
+		//entities with a Transform get drawn where it says, the rest wherever their Physics is
+		let render_options = SystemOptions::new().stage(Stage::RenderPrep).parallel()
+			.link("Physics", "position", "position")
+			.link("Physics", "angle", "rotation")
+			.link("Transform", "position", "position")
+			.link("Transform", "rotation", "rotation")
+			.link("Transform", "scale", "scale");
+		world.add_native_system(ctx, Box::new(render::RenderSystem::new()), "Render", render_options)?;
+		let text_options = SystemOptions::new().stage(Stage::RenderPrep).parallel()
+			.link("Physics", "position", "position")
+			.link("Transform", "position", "position");
+		world.add_native_system(ctx, Box::new(text::TextSystem::new()), "Text", text_options)?;
+		Ok(())
+	})?;
 
 	//some nice functions
 	lua.context(|ctx| {
@@ -83,77 +158,153 @@ fn main() ->  Result<(), Box<Error>> {
 		//load scripts in scripts/ directory
 		for s in Scripts::iter() {
 			println!("loading {:?}", s);
-			ctx.load(&Scripts::get(&s).unwrap()).exec().unwrap();
+			if let Err(e) = ctx.load(&Scripts::get(&s).unwrap()).exec() {
+				load_errors.push(format!("{}: {}", s, error::describe_lua_error(&e)));
+			}
 		}
 	});
 
+	//luasys --replay <file>: run the scripts against a recorded session (see --record) without opening a window
+	if args.get(1).map(String::as_str) == Some("--replay") {
+		let path = args.get(2).ok_or("--replay needs a recording to play")?;
+		let recording: input::Recording = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+		let ticks = recording.ticks;
+		let mut recording = Some(recording);
+		w.write_native_system("Input", |i: &mut input::InputSystem| i.play(recording.take().unwrap()))?;
+		for e in &load_errors {
+			eprintln!("{}", e);
+		}
+		let mut failures = 0;
+		lua.context(|ctx| {
+			for tick in 0..ticks {
+				if let Err(e) = w.0.tick(ctx, TIMESTEP) {
+					eprintln!("tick {}: {}", tick, e);
+					failures += 1;
+				}
+			}
+		});
+		println!("replayed {} ticks, {} failed", ticks, failures);
+		if failures > 0 || !load_errors.is_empty() {
+			return Err(format!("{} ticks failed, {} errors while loading", failures, load_errors.len()).into());
+		}
+		return Ok(());
+	}
+	//luasys --record <file>: save everything the Input system gets to a file on exit, for --replay
+	let record = match args.get(1).map(String::as_str) {
+		Some("--record") => Some(args.get(2).ok_or("--record needs a file to write")?.clone()),
+		_ => None,
+	};
+	if record.is_some() {
+		w.write_native_system("Input", |i: &mut input::InputSystem| i.start_recording())?;
+	}
+
+	let sdl_context = sdl2::init()?;
+	let mut sdl_renderer = sdl_renderer::SdlRenderer::new(&sdl_context, "luasys", 640, 400)?;
+	sdl_renderer.load_sprites(&images)?;
+	for (name, bytes) in fonts {
+		if let Err(e) = sdl_renderer.fonts.load(&name, bytes) {
+			load_errors.push(e);
+		}
+	}
+
+	let (screen_width, screen_height) = (sdl_renderer.screen_width, sdl_renderer.screen_height);
+	w.write_native_system("Camera", |c: &mut camera::CameraSystem| {
+		c.screen_width = screen_width;
+		c.screen_height = screen_height;
+	})?;
+
+	//the bitmap font only has ASCII, so it's just a fallback
+	let mut term = if sdl_renderer.fonts.contains("DejaVuSansMono") {
+		Terminal::new(sdl_renderer.screen_height, "DejaVuSansMono", 7, 12)
+	} else {
+		Terminal::new(sdl_renderer.screen_height, "font-oldschool", 7, 9)
+	};
+	for e in &load_errors {
+		term.log(e);
+	}
 
-	//TODO: make a module for input handling, similar to sdl_renderer
     let mut event_pump = sdl_context.event_pump()?;
+    let game_controllers = sdl_context.game_controller()?;
+    //closed when they're dropped
+    let mut controllers = Vec::new();
+    let mut previous_time = Instant::now();
+    let mut accumulator = 0.0;
     'running: loop {
         //parse events
         use sdl2::event::Event;
         use sdl2::keyboard::Keycode;
         use sdl2::mouse::MouseButton;
         for event in event_pump.poll_iter() {
+            //while the console is open keys go to it instead, but letting go of one still counts
+            let to_game = !term.is_active() || !matches!(event, Event::KeyDown{..} | Event::TextInput{..} | Event::TextEditing{..});
+            //the console and quitting go by their actions too, but right away rather than on the next tick
+            let mut triggered = Vec::new();
+            if let Some(e) = input::InputEvent::from_sdl(&event) {
+            	w.write_native_system("Input", |i: &mut input::InputSystem| {
+            		//a key pressed to rebind something shouldn't also do what it used to
+            		if !i.actions.is_listening() {
+            			triggered = i.actions.triggered_by(&e).into_iter().map(String::from).collect();
+            		}
+            		if to_game {
+            			i.push(e.clone());
+            		}
+            	})?;
+            }
+            //typing into the console shouldn't quit just because quit got bound to a letter
+            if triggered.iter().any(|a| a == "quit") && !term.is_active() {
+            	break 'running;
+            }
+            if triggered.iter().any(|a| a == "toggle_console") {
+            	if term.is_active() {
+            		sdl_renderer.video.text_input().stop();
+            	} else {
+            		sdl_renderer.video.text_input().start();
+            	}
+            	term.toggle();
+            	continue;
+            }
             match event {
-                Event::Quit{..} |
-                Event::KeyDown{keycode: Some(Keycode::Escape), ..} => {
+                Event::Quit{..} => {
                     break 'running
                 },
+                //editing keys for the console, not actions
                 Event::KeyDown{keycode: Some(keycode), ..} => {
                 	match keycode {
-                		Keycode::Left => w.write_native_system("Render", |r: &mut render::RenderSystem| {r.camera_x -= 16}),
-                		Keycode::Right => w.write_native_system("Render", |r: &mut render::RenderSystem| {r.camera_x += 16}),
-                		Keycode::Up => w.write_native_system("Render", |r: &mut render::RenderSystem| {r.camera_y -= 16}),
-                		Keycode::Down => w.write_native_system("Render", |r: &mut render::RenderSystem| {r.camera_y += 16}),
-                		Keycode::Backquote => {
-		                	if term.is_active() {
-		                		sdl_renderer.video.text_input().stop();
-		                	} else {
-		                		sdl_renderer.video.text_input().start();
-		                	}
-		                	term.toggle();
-                		},
-                		Keycode::Return => {
-			            	if term.is_active() {
-			            		sdl_renderer.video.text_input().stop();
-			                	lua.context(|ctx|{
-			                		term.process_commandline(ctx);
-			                	});
-			                	//don't deactivate term unless they press backquote again
-			                	sdl_renderer.video.text_input().start();
-			            	}
+                		Keycode::Return if term.is_active() => {
+			            	sdl_renderer.video.text_input().stop();
+			                lua.context(|ctx|{
+			                	term.process_commandline(ctx);
+			                });
+			                //don't deactivate term unless they press backquote again
+			                sdl_renderer.video.text_input().start();
                 		},
-                		Keycode::Backspace => {
-				        	if term.is_active() {
-				        		term.backspace();
-				        	}
+                		Keycode::Backspace if term.is_active() => {
+				        	term.backspace();
                 		},
                 		_ => {}
                 	}
                 },
-                //event::mouseclick: go through entities, see if mouse is between x,y and w,h
-                Event::MouseButtonDown{mouse_btn: MouseButton::Left, x: mx, y: my, ..} => {
-                	if term.is_active() {
-                		lua.context(|ctx| {
-							for i in 0..w.size() {
-								if let (Ok(x), Ok(y), Ok(w), Ok(h)) = (
-										ctx.load(&format!("world:get('Render', {}).x", i)).eval::<f64>(), 
-										ctx.load(&format!("world:get('Render', {}).y", i)).eval::<f64>(), 
-										ctx.load(&format!("world:get('Render', {}).width", i)).eval::<u32>(),
-										ctx.load(&format!("world:get('Render', {}).height", i)).eval::<u32>()) {
-									let (x, y, w, h) = (x as i32, y as i32, w as i32, h as i32);
-									if mx > x && mx < x+w && my > y && my < y+h {
-										println!("id: {}", i);
-										term.append_commandline(&i.to_string());
-										break;
-									}
-								}
-							}
-						});
+                //event::mouseclick: put the id of whatever's under the mouse into the terminal
+                Event::MouseButtonDown{mouse_btn: MouseButton::Left, x: mx, y: my, ..} if term.is_active() => {
+                	//goes by what's drawn there, so it works the same for things without a Collision shape
+                	let (screen_width, screen_height) = (sdl_renderer.screen_width, sdl_renderer.screen_height);
+                	let mut slot = None;
+                	w.read_native_system("Render", |r: &render::RenderSystem| slot = r.pick(mx as f64, my as f64, screen_width, screen_height))?;
+                	if let Some(slot) = slot {
+                		let id = w.members("Render")?[slot].to_bits();
+                		term.append_commandline(&id.to_string());
+                	}
+                }
+
+                Event::ControllerDeviceAdded{which, ..} => {
+                	match game_controllers.open(which) {
+                		Ok(controller) => controllers.push(controller),
+                		Err(e) => term.log(&format!("can't open controller {}: {}", which, e)),
                 	}
                 }
+                Event::ControllerDeviceRemoved{which, ..} => {
+                	controllers.retain(|c| c.instance_id() != which);
+                }
 
                 Event::TextEditing{text, ..} => {
                 	//println!("editing: {}", text);
@@ -167,33 +318,72 @@ fn main() ->  Result<(), Box<Error>> {
             }
         }
 
-        lua.context(|ctx| {
-			//LOGIC
-			ctx.load("world:tick()").exec().unwrap();
+        let now = Instant::now();
+        accumulator += now.duration_since(previous_time).as_secs_f64().min(MAX_FRAME_TIME);
+        previous_time = now;
+
+        lua.context(|ctx| -> Result<(), error::WorldError> {
             ctx.globals().set("mouse_x", event_pump.mouse_state().x()).unwrap();
             ctx.globals().set("mouse_y", event_pump.mouse_state().y()).unwrap();
-        });
+			//LOGIC
+			while accumulator >= TIMESTEP {
+				w.write_native_system("Render", |r: &mut render::RenderSystem| r.begin_step())?;
+				if let Err(e) = w.0.tick(ctx, TIMESTEP) {
+					term.log(&e.to_string());
+				}
+				w.write_native_system("Input", |i: &mut input::InputSystem| {
+					for e in i.take_errors() {
+						term.log(&e);
+					}
+				})?;
+				accumulator -= TIMESTEP;
+			}
+			Ok(())
+        })?;
 
 
 		//RENDER
 
 		sdl_renderer.clear(200, 200, 255);
 
+		let mut view = camera::View::default();
+		w.write_native_system("Camera", |c: &mut camera::CameraSystem| {
+			c.alpha = accumulator / TIMESTEP;
+			view = c.view();
+		})?;
 		w.write_native_system("Render", |r: &mut render::RenderSystem| {
-			w.write_native_system("Physics", |ph: &mut physics::PhysicsSystem| {
-				for (_, &e) in &ph.entities {
-					r.set_position(e, ph.positions[e].x, ph.positions[e].y);
-					r.set_rotation(e, ph.angles[e]);
-				}
+			r.alpha = accumulator / TIMESTEP;
+			r.view = view;
+			let mut drawn = false;
+			let _ = w.0.read_native_system("Tilemap", |t: &tilemap::TilemapSystem| {
+				r.render_with(&mut sdl_renderer, Some(t));
+				drawn = true;
 			});
-			r.render(&mut sdl_renderer);
-			//r.render(&mut r);
-		});
+			if !drawn {
+				r.render(&mut sdl_renderer);
+			}
+		})?;
+		w.write_native_system("Text", |t: &mut text::TextSystem| {
+			t.view = view;
+			t.render(&mut sdl_renderer);
+		})?;
 
+		//a glyph cache that can't draw will fail every frame, the console only shows it the once
+		if let Some(e) = sdl_renderer.text_error.take() {
+			term.log(&format!("can't draw text: {}", e));
+		}
 		term.render(&mut sdl_renderer);
 
 		sdl_renderer.present();
     }
 
+	if let Some(path) = record {
+		let mut recording = None;
+		w.write_native_system("Input", |i: &mut input::InputSystem| recording = i.stop_recording())?;
+		if let Some(recording) = recording {
+			std::fs::write(&path, serde_json::to_string(&recording)?)?;
+			println!("recorded {} ticks of input to {}", recording.ticks, path);
+		}
+	}
 	Ok(())
 }
@@ -1,6 +1,7 @@
-use crate::world::{NativeSystem, World};
+use crate::entity::Entity;
+use crate::error::WorldError;
+use crate::world::{slot_count, Field, LuaApi, NativeSystem, World};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
 
 #[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct Vector2 {
@@ -18,115 +19,391 @@ pub struct PhysicsObject {
     pub acceleration: Vector2,
     #[serde(default)]
     pub angle: f64,
+    //degrees per second, like angle
+    #[serde(default)]
+    pub angular_velocity: f64,
+    //0 or less means infinitely heavy: forces, impulses and gravity do nothing to it
+    #[serde(default = "one")]
+    pub mass: f64,
+    //how much speed is kept when bouncing off something, 0..1
+    #[serde(default)]
+    pub restitution: f64,
+    #[serde(default)]
+    pub friction: f64,
+    //fraction of the speed lost per second, roughly
+    #[serde(default)]
+    pub linear_damping: f64,
+    #[serde(default)]
+    pub angular_damping: f64,
+    //solid bodies get pushed apart by the CollisionSystem
+    #[serde(default)]
+    pub solid: bool,
+    //"static", "kinematic" or "dynamic" (the default), see BodyKind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<BodyKind>,
+    //older way of asking for a kinematic body, only read if kind isn't given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dynamic: Option<bool>,
 }
 
-//exposed position + angle for rendering
+impl PhysicsObject {
+    fn body_kind(&self) -> BodyKind {
+        match (self.kind, self.dynamic) {
+            (Some(kind), _) => kind,
+            (None, Some(false)) => BodyKind::Kinematic,
+            (None, _) => BodyKind::Dynamic,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyKind {
+    //never moves and is skipped every tick, for walls and floors
+    Static,
+    //moves by its own (scripted) velocity, but collisions, forces and gravity never push it
+    Kinematic,
+    //everything applies
+    #[default]
+    Dynamic,
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+//how a body reacts to forces and collisions
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Material {
+    pub mass: f64,
+    pub restitution: f64,
+    pub friction: f64,
+    pub linear_damping: f64,
+    pub angular_damping: f64,
+}
+
+impl Material {
+    pub fn inverse_mass(&self) -> f64 {
+        if self.mass > 0.0 {
+            1.0 / self.mass
+        } else {
+            0.0
+        }
+    }
+}
+
+//exposed position + angle for rendering, velocity + flags + materials for collisions
+//indexed by slot, see World::read_members("Physics")
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PhysicsSystem {
-    pub entities: HashMap<usize, usize>,
     pub positions: Vec<Vector2>,
-    velocities: Vec<Vector2>,
+    pub velocities: Vec<Vector2>,
     accelerations: Vec<Vector2>,
     pub angles: Vec<f64>,
+    angular_velocities: Vec<f64>,
+    pub materials: Vec<Material>,
+    //added up by apply_force, used up by the next tick
+    forces: Vec<Vector2>,
+    pub solid: Vec<bool>,
+    pub kinds: Vec<BodyKind>,
+    //pixels per second squared, applies to every dynamic body
+    pub gravity: Vector2,
+    //bumped whenever a static body appears, disappears or moves, so other systems can cache them
+    #[serde(skip)]
+    pub statics_changed: u64,
+}
+
+impl PhysicsSystem {
+    //bodies that forces/impulses/collisions are allowed to move
+    pub fn inverse_mass(&self, slot: usize) -> f64 {
+        if self.kinds[slot] == BodyKind::Dynamic {
+            self.materials[slot].inverse_mass()
+        } else {
+            0.0
+        }
+    }
 }
 
 impl NativeSystem for PhysicsSystem {
     fn new() -> PhysicsSystem {
         PhysicsSystem {
-            entities: HashMap::new(),
             positions: Vec::new(),
             velocities: Vec::new(),
             accelerations: Vec::new(),
             angles: Vec::new(),
+            angular_velocities: Vec::new(),
+            materials: Vec::new(),
+            forces: Vec::new(),
+            solid: Vec::new(),
+            kinds: Vec::new(),
+            gravity: Vector2::default(),
+            statics_changed: 0,
         }
     }
-    fn globals(&self, ctx: rlua::Context) {
-        ctx.load(
-            r#"
-			function set_position(id, x, y) 
-				world:entity_update(id, {'Physics'}, function(p) 
-					p.position.x = x 
-					p.position.y = y
-					return p 
-				end) 
-			end"#,
-        )
-        .exec()
-        .unwrap();
+    fn lua_api(&self, api: &LuaApi) -> rlua::Result<()> {
+        api.add_function("set_position", |_, ph: &mut PhysicsSystem, members, (id, x, y): (Entity, f64, f64)| {
+            if let Some(i) = members.slot(id) {
+                if ph.kinds[i] == BodyKind::Static {
+                    ph.statics_changed += 1;
+                }
+                ph.positions[i] = Vector2 { x, y };
+            }
+            Ok(())
+        })?;
+        api.add_function("get_position", |_, ph: &mut PhysicsSystem, members, id: Entity| {
+            Ok(members.slot(id).map(|i| ph.positions[i]).map_or((None, None), |p| (Some(p.x), Some(p.y))))
+        })?;
+        api.add_function("set_velocity", |_, ph: &mut PhysicsSystem, members, (id, x, y): (Entity, f64, f64)| {
+            if let Some(i) = members.slot(id) {
+                ph.velocities[i] = Vector2 { x, y };
+            }
+            Ok(())
+        })?;
+        api.add_function("get_velocity", |_, ph: &mut PhysicsSystem, members, id: Entity| {
+            Ok(members.slot(id).map(|i| ph.velocities[i]).map_or((None, None), |v| (Some(v.x), Some(v.y))))
+        })?;
+        api.add_function("set_acceleration", |_, ph: &mut PhysicsSystem, members, (id, x, y): (Entity, f64, f64)| {
+            if let Some(i) = members.slot(id) {
+                ph.accelerations[i] = Vector2 { x, y };
+            }
+            Ok(())
+        })?;
+        api.add_function("set_angle", |_, ph: &mut PhysicsSystem, members, (id, angle): (Entity, f64)| {
+            if let Some(i) = members.slot(id) {
+                ph.angles[i] = angle;
+            }
+            Ok(())
+        })?;
+        api.add_function("get_angle", |_, ph: &mut PhysicsSystem, members, id: Entity| {
+            Ok(members.slot(id).map(|i| ph.angles[i]))
+        })?;
+        api.add_function("set_angular_velocity", |_, ph: &mut PhysicsSystem, members, (id, speed): (Entity, f64)| {
+            if let Some(i) = members.slot(id) {
+                ph.angular_velocities[i] = speed;
+            }
+            Ok(())
+        })?;
+        api.add_function("get_angular_velocity", |_, ph: &mut PhysicsSystem, members, id: Entity| {
+            Ok(members.slot(id).map(|i| ph.angular_velocities[i]))
+        })?;
+        //a push that lasts for the next tick, scaled by dt / mass
+        api.add_function("apply_force", |_, ph: &mut PhysicsSystem, members, (id, x, y): (Entity, f64, f64)| {
+            if let Some(i) = members.slot(id) {
+                ph.forces[i].x += x;
+                ph.forces[i].y += y;
+            }
+            Ok(())
+        })?;
+        //an instant change in velocity, scaled by 1 / mass
+        api.add_function("apply_impulse", |_, ph: &mut PhysicsSystem, members, (id, x, y): (Entity, f64, f64)| {
+            if let Some(i) = members.slot(id) {
+                let inverse_mass = ph.inverse_mass(i);
+                ph.velocities[i].x += x * inverse_mass;
+                ph.velocities[i].y += y * inverse_mass;
+            }
+            Ok(())
+        })?;
+        api.add_function("set_gravity", |_, ph: &mut PhysicsSystem, _, (x, y): (f64, f64)| {
+            ph.gravity = Vector2 { x, y };
+            Ok(())
+        })?;
+        api.add_function("get_gravity", |_, ph: &mut PhysicsSystem, _, ()| Ok((ph.gravity.x, ph.gravity.y)))?;
+        Ok(())
+    }
+    fn globals(&self, ctx: rlua::Context) -> rlua::Result<()> {
+        //old names, kept around for scripts that still use them
         ctx.load(
             r#"
-			function set_velocity(id, x, y)
-				world:entity_update(id, {'Physics'}, function(p)
-					p.velocity.x = x
-					p.velocity.y = y
-					return p
-				end)
-			end"#,
+			set_position = Physics.set_position
+			set_velocity = Physics.set_velocity"#,
         )
         .exec()
-        .unwrap();
     }
-    fn tick(&mut self, _: &World) {
+    //units are pixels per second (and per second squared)
+    //semi-implicit euler: velocity first, then position with the new velocity
+    fn tick(&mut self, _: &World, dt: f64) {
         //println!("{:?}", self);
-        for i in 0..self.entities.len() {
-            //self.velocities[i].y += 9.81 / 96.0; //32 pixels ~= 1 foot; 96 pixels = 1 meter
-            self.velocities[i].x += self.accelerations[i].x;
-            self.velocities[i].y += self.accelerations[i].y;
-            self.positions[i].x += self.velocities[i].x;
-            self.positions[i].y += self.velocities[i].y;
-            //self.angles[i] += 2.0 / self.velocities[i].x; //really basic visual effect
+        for i in 0..self.positions.len() {
+            if self.kinds[i] == BodyKind::Static {
+                continue;
+            }
+            //used up either way, kinematic bodies just ignore theirs
+            let force = std::mem::take(&mut self.forces[i]);
+            //kinematic bodies only go where their velocity takes them
+            if self.kinds[i] == BodyKind::Dynamic {
+                let inverse_mass = self.inverse_mass(i);
+                //gravity = 9.81 * 96.0 for earth-ish; 32 pixels ~= 1 foot; 96 pixels = 1 meter
+                let gravity = if inverse_mass > 0.0 { self.gravity } else { Vector2::default() };
+                self.velocities[i].x += (self.accelerations[i].x + gravity.x + force.x * inverse_mass) * dt;
+                self.velocities[i].y += (self.accelerations[i].y + gravity.y + force.y * inverse_mass) * dt;
+                //dividing instead of multiplying by (1 - damping * dt) so big damping values can't flip the direction
+                let Material { linear_damping, angular_damping, .. } = self.materials[i];
+                self.velocities[i].x /= 1.0 + linear_damping * dt;
+                self.velocities[i].y /= 1.0 + linear_damping * dt;
+                self.angular_velocities[i] /= 1.0 + angular_damping * dt;
+            }
+            self.positions[i].x += self.velocities[i].x * dt;
+            self.positions[i].y += self.velocities[i].y * dt;
+            self.angles[i] += self.angular_velocities[i] * dt;
         }
     }
-    fn save(&self) -> serde_json::Value {
-        serde_json::to_value(self).unwrap()
+    fn read_field(&self, slot: usize, field: &str) -> Option<Field> {
+        match field {
+            "position" => Some(Field::Vector(self.positions[slot].x, self.positions[slot].y)),
+            "velocity" => Some(Field::Vector(self.velocities[slot].x, self.velocities[slot].y)),
+            "angle" => Some(Field::Number(self.angles[slot])),
+            _ => None,
+        }
+    }
+    fn save(&self) -> Result<serde_json::Value, WorldError> {
+        serde_json::to_value(self).map_err(WorldError::parse)
+    }
+    fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError> {
+        let statics_changed = self.statics_changed + 1;
+        *self = serde_json::from_value(state).map_err(WorldError::parse)?;
+        self.statics_changed = statics_changed;
+        Ok(())
+    }
+    fn slots(&self) -> Result<usize, WorldError> {
+        slot_count(&[
+            ("positions", self.positions.len()),
+            ("velocities", self.velocities.len()),
+            ("accelerations", self.accelerations.len()),
+            ("angles", self.angles.len()),
+            ("angular_velocities", self.angular_velocities.len()),
+            ("materials", self.materials.len()),
+            ("forces", self.forces.len()),
+            ("solid", self.solid.len()),
+            ("kinds", self.kinds.len()),
+        ])
     }
-    fn spawn(&mut self, entity: usize, object: rlua::Value) {
-        if let Ok(PhysicsObject {
+    fn spawn<'lua>(&mut self, _: rlua::Context<'lua>, _: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError> {
+        let object: PhysicsObject = rlua_serde::from_value(object).map_err(WorldError::parse)?;
+        let kind = object.body_kind();
+        let PhysicsObject {
             position,
             velocity,
             acceleration,
             angle,
-        }) = rlua_serde::from_value(object)
-        {
-            self.entities.insert(entity, self.positions.len());
-            self.positions.push(position);
-            self.velocities.push(velocity);
-            self.accelerations.push(acceleration);
-            self.angles.push(angle);
-        } else {
-            panic!("Could not parse object")
+            angular_velocity,
+            mass,
+            restitution,
+            friction,
+            linear_damping,
+            angular_damping,
+            solid,
+            ..
+        } = object;
+        self.positions.push(position);
+        self.velocities.push(velocity);
+        self.accelerations.push(acceleration);
+        self.angles.push(angle);
+        self.angular_velocities.push(angular_velocity);
+        self.materials.push(Material { mass, restitution, friction, linear_damping, angular_damping });
+        self.forces.push(Vector2::default());
+        self.solid.push(solid);
+        self.kinds.push(kind);
+        if kind == BodyKind::Static {
+            self.statics_changed += 1;
         }
+        Ok(())
     }
-    fn get<'lua>(&self, ctx: rlua::Context<'lua>, entity: usize) -> rlua::Value<'lua> {
-        if let Some(&i) = self.entities.get(&entity) {
-            rlua_serde::to_value(
-                ctx,
-                PhysicsObject {
-                    position: self.positions[i],
-                    velocity: self.velocities[i],
-                    acceleration: self.accelerations[i],
-                    angle: self.angles[i],
-                },
-            )
-            .unwrap()
-        } else {
-            rlua::Value::Nil
+    fn despawn(&mut self, slot: usize) {
+        self.positions.swap_remove(slot);
+        self.velocities.swap_remove(slot);
+        self.accelerations.swap_remove(slot);
+        self.angles.swap_remove(slot);
+        self.angular_velocities.swap_remove(slot);
+        self.materials.swap_remove(slot);
+        self.forces.swap_remove(slot);
+        self.solid.swap_remove(slot);
+        if self.kinds.swap_remove(slot) == BodyKind::Static {
+            self.statics_changed += 1;
         }
     }
-    fn set<'lua>(&mut self, entity: usize, value: rlua::Value<'lua>) {
-        if let Some(&i) = self.entities.get(&entity) {
-            if let Ok(PhysicsObject {
-                position,
-                velocity,
-                acceleration,
-                angle,
-            }) = rlua_serde::from_value(value)
-            {
-                self.positions[i] = position;
-                self.velocities[i] = velocity;
-                self.accelerations[i] = acceleration;
-                self.angles[i] = angle;
-            }
+    fn get<'lua>(&self, ctx: rlua::Context<'lua>, i: usize) -> Result<rlua::Value<'lua>, WorldError> {
+        let Material { mass, restitution, friction, linear_damping, angular_damping } = self.materials[i];
+        rlua_serde::to_value(
+            ctx,
+            PhysicsObject {
+                position: self.positions[i],
+                velocity: self.velocities[i],
+                acceleration: self.accelerations[i],
+                angle: self.angles[i],
+                angular_velocity: self.angular_velocities[i],
+                mass,
+                restitution,
+                friction,
+                linear_damping,
+                angular_damping,
+                solid: self.solid[i],
+                kind: Some(self.kinds[i]),
+                dynamic: None,
+            },
+        )
+        .map_err(WorldError::parse)
+    }
+    fn set<'lua>(&mut self, _: rlua::Context<'lua>, i: usize, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+        let object: PhysicsObject = rlua_serde::from_value(value).map_err(WorldError::parse)?;
+        let kind = object.body_kind();
+        let PhysicsObject {
+            position,
+            velocity,
+            acceleration,
+            angle,
+            angular_velocity,
+            mass,
+            restitution,
+            friction,
+            linear_damping,
+            angular_damping,
+            solid,
+            ..
+        } = object;
+        self.positions[i] = position;
+        self.velocities[i] = velocity;
+        self.accelerations[i] = acceleration;
+        self.angles[i] = angle;
+        self.angular_velocities[i] = angular_velocity;
+        self.materials[i] = Material { mass, restitution, friction, linear_damping, angular_damping };
+        self.solid[i] = solid;
+        if self.kinds[i] == BodyKind::Static || kind == BodyKind::Static {
+            self.statics_changed += 1;
         }
+        self.kinds[i] = kind;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_dynamic_bodies_feel_forces_gravity_and_damping() {
+        rlua::Lua::new().context(|ctx| {
+            let world = World::new();
+            let mut ph = PhysicsSystem::new();
+            ph.gravity = Vector2 { x: 0.0, y: 100.0 };
+            for (index, kind) in ["dynamic", "kinematic"].iter().enumerate() {
+                let object = ctx
+                    .load(&format!(
+                        "{{kind = '{}', velocity = {{x = 10, y = 0}}, acceleration = {{x = 5, y = 0}}, angular_velocity = 1, linear_damping = 1, angular_damping = 1}}",
+                        kind
+                    ))
+                    .eval()
+                    .unwrap();
+                ph.spawn(ctx, Entity { index: index as u32, generation: 0 }, object).unwrap();
+                ph.forces[index] = Vector2 { x: 0.0, y: -50.0 };
+            }
+            ph.tick(&world, 1.0);
+            //pushed around, then slowed down by half
+            assert_eq!((ph.velocities[0].x, ph.velocities[0].y, ph.angular_velocities[0]), (7.5, 25.0, 0.5));
+            //straight on at the speed it was set to
+            assert_eq!((ph.velocities[1].x, ph.velocities[1].y, ph.angular_velocities[1]), (10.0, 0.0, 1.0));
+            assert_eq!((ph.positions[1].x, ph.positions[1].y, ph.angles[1]), (10.0, 0.0, 1.0));
+            //its force doesn't wait around for it to turn dynamic
+            assert_eq!((ph.forces[1].x, ph.forces[1].y), (0.0, 0.0));
+        });
     }
 }
@@ -1,5 +1,9 @@
 use serde::{Serialize, Deserialize};
-use crate::world::{World, NativeSystem};
+use crate::world::{World, NativeSystem, LuaApi, Field, slot_count};
+use crate::entity::Entity;
+use crate::error::WorldError;
+use crate::camera::View;
+use crate::physics::Vector2;
 use std::collections::HashMap;
 
 //use sdl2::pixels::Color;
@@ -24,6 +28,55 @@ pub struct RenderInfo {
 	pub rotation: f64,
 	#[serde(default)]
 	pub z_index: i32,
+	#[serde(default = "main_layer")]
+	pub layer: String,
+}
+
+fn main_layer() -> String {
+	"main".to_string()
+}
+fn unit() -> Vector2 {
+	Vector2{x: 1.0, y: 1.0}
+}
+
+//sprites get drawn layer by layer, lowest order first, and by z_index inside a layer
+//e.g. Render.set_layer("background", {order = -2, parallax = {x = 0.5, y = 0.5}})
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Layer {
+	#[serde(default)]
+	pub name: String,
+	#[serde(default)]
+	pub order: i32,
+	//how much of the camera's movement the layer follows; 0 stays put, 1 is the same as gameplay
+	#[serde(default = "unit")]
+	pub parallax: Vector2,
+	//positions are screen pixels and the camera doesn't touch it, for the HUD
+	#[serde(default)]
+	pub screen_space: bool,
+}
+
+impl Layer {
+	fn new(name: &str, order: i32, screen_space: bool) -> Layer {
+		Layer{name: name.to_string(), order, parallax: unit(), screen_space}
+	}
+	//what this layer gets drawn through when the camera sees view
+	fn view(&self, view: &View, screen_width: u32, screen_height: u32) -> View {
+		if self.screen_space {
+			return View::screen(screen_width, screen_height);
+		}
+		//parallax is measured from where the camera is with nothing moving it, so 0 still lines up with the screen
+		let (home_x, home_y) = (view.viewport.width / 2.0, view.viewport.height / 2.0);
+		View{x: home_x + (view.x - home_x) * self.parallax.x, y: home_y + (view.y - home_y) * self.parallax.y, ..*view}
+	}
+}
+
+fn default_layers() -> Vec<Layer> {
+	vec![
+		Layer::new("background", -2, false),
+		Layer::new("map", -1, false),
+		Layer::new("main", 0, false),
+		Layer::new("hud", 1, true),
+	]
 }
 
 
@@ -46,7 +99,7 @@ pub struct Animation {
 	#[serde(default)] //if end = 0 then end = texture_width / frame_width
 	pub last: u32,
 	#[serde(default)]
-	pub speed: f64,
+	pub speed: f64, //frames per second
 	#[serde(default = "fone")]
 	pub current_frame: f64, //it's actually a float here but gets rounded on get/set
 }
@@ -67,55 +120,163 @@ pub struct AnimationComponent {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct RenderSystem {
-	//hashmap for quickest lookup
-	sprites: HashMap<usize, String>,
-	frames: HashMap<usize, Frame>,
-	ordering: HashMap<usize, i32>,
-	rotations: HashMap<usize, f64>,
-	//animations require indirect lookup, but faster iteration via Vec
-	entities: HashMap<usize, usize>,
+	//indexed by slot, see World::read_members("Render")
+	sprites: Vec<String>,
+	frames: Vec<Frame>,
+	ordering: Vec<i32>,
+	//saves from before layers don't have these, everything in them goes on main
+	#[serde(default)]
+	sprite_layers: Vec<String>,
+	rotations: Vec<f64>,
 	animations: Vec<AnimationComponent>,
+	//where each frame was at the start of the current fixed step, rebuilt from frames on load
+	#[serde(skip)]
+	previous: Vec<(f64, f64)>,
+	//only ever set through a link (from a Transform, usually); negative flips the sprite
+	#[serde(skip)]
+	scales: Vec<(f64, f64)>,
 
-	pub camera_x: i32,
-	pub camera_y: i32,
+	//the active camera's, set every frame before rendering
+	#[serde(skip)]
+	pub view: View,
+	//how far the real time is between the last fixed step and the next one, 0..1
+	#[serde(skip)]
+	pub alpha: f64,
+	//by name; sprites on a layer that isn't here go in at order 0, like main
+	#[serde(default = "default_layers")]
+	layers: Vec<Layer>,
 }
 
 impl RenderSystem {
-	pub fn set_position(&mut self, entity: usize, x: f64, y: f64) {
-		if let Some(f) = self.frames.get_mut(&entity) {
+	pub fn set_position(&mut self, slot: usize, x: f64, y: f64) {
+		if let Some(f) = self.frames.get_mut(slot) {
 			f.x = x;
 			f.y = y;	
 		}
 	}
-	pub fn set_rotation(&mut self, entity: usize, rot: f64) {
-		if let Some(angle) = self.rotations.get_mut(&entity) {
+	pub fn set_rotation(&mut self, slot: usize, rot: f64) {
+		if let Some(angle) = self.rotations.get_mut(slot) {
 			*angle = rot;
 		}
 	}
+	fn layer(&self, name: &str) -> Layer {
+		self.layers.iter().find(|l| l.name == name).cloned().unwrap_or_else(|| Layer::new(name, 0, false))
+	}
+	//call before every fixed step, positions get drawn between these and whatever the step sets
+	pub fn begin_step(&mut self) {
+		for (previous, frame) in self.previous.iter_mut().zip(self.frames.iter()) {
+			*previous = (frame.x, frame.y);
+		}
+	}
+	//slot of the sprite drawn on top at a screen position, going by where it was last drawn
+	//sprites sized by their animation have no size until they've been drawn once, so they can't be hit before that
+	pub fn pick(&self, x: f64, y: f64, screen_width: u32, screen_height: u32) -> Option<usize> {
+		(0..self.frames.len()).filter(|&i| {
+			let view = self.layer(&self.sprite_layers[i]).view(&self.view, screen_width, screen_height);
+			let (px, py) = view.to_world(x, y);
+			let (previous_x, previous_y) = self.previous[i];
+			let frame = &self.frames[i];
+			let (dx, dy) = (px - (previous_x + (frame.x - previous_x) * self.alpha), py - (previous_y + (frame.y - previous_y) * self.alpha));
+			//undo the sprite's own rotation, the view already took care of the camera's
+			let (sin, cos) = self.rotations[i].to_radians().sin_cos();
+			let (dx, dy) = (dx * cos + dy * sin, dy * cos - dx * sin);
+			let (scale_x, scale_y) = self.scales[i];
+			dx.abs() * 2.0 <= frame.width as f64 * scale_x.abs() && dy.abs() * 2.0 <= frame.height as f64 * scale_y.abs()
+		}).max_by_key(|&i| (self.layer(&self.sprite_layers[i]).order, self.ordering[i]))
+	}
 }
 
 impl NativeSystem for RenderSystem {
 	fn new() -> RenderSystem {
-	    RenderSystem{camera_x: 0, camera_y: 0, sprites: HashMap::new(), entities: HashMap::new(), animations: Vec::new(), frames: HashMap::new(), ordering: HashMap::new(), rotations: HashMap::new()}
-	}
-	fn spawn(&mut self, entity: usize, object: rlua::Value) {
-		if let Ok(RenderInfo{sprite, animations, animation, x, y, width, height, z_index, rotation}) = rlua_serde::from_value(object) {
-			self.sprites.insert(entity, sprite);
-			self.frames.insert(entity, Frame{x, y, width, height});
-			self.ordering.insert(entity, z_index);
-			self.entities.insert(entity, self.entities.len());
-			self.animations.push(AnimationComponent{animations, animation});
-			self.rotations.insert(entity, rotation);
-        }
-	}
-	fn tick(&mut self, _: &World) {
+	    RenderSystem{view: View::default(), alpha: 0.0, sprites: Vec::new(), animations: Vec::new(), frames: Vec::new(), previous: Vec::new(), scales: Vec::new(), ordering: Vec::new(), sprite_layers: Vec::new(), rotations: Vec::new(), layers: default_layers()}
+	}
+	fn lua_api(&self, api: &LuaApi) -> rlua::Result<()> {
+		api.add_function("set_position", |_, r: &mut RenderSystem, members, (id, x, y): (Entity, f64, f64)| {
+			if let Some(i) = members.slot(id) {
+				r.set_position(i, x, y);
+			}
+			Ok(())
+		})?;
+		api.add_function("get_position", |_, r: &mut RenderSystem, members, id: Entity| {
+			Ok(members.slot(id).map_or((None, None), |i| (Some(r.frames[i].x), Some(r.frames[i].y))))
+		})?;
+		//makes the layer if there isn't one by that name yet, otherwise replaces its settings
+		api.add_function("set_layer", |_, r: &mut RenderSystem, _, (name, options): (String, rlua::Value)| {
+			let mut layer: Layer = rlua_serde::from_value(options)?;
+			layer.name = name;
+			match r.layers.iter_mut().find(|l| l.name == layer.name) {
+				Some(existing) => *existing = layer,
+				None => r.layers.push(layer),
+			}
+			Ok(())
+		})?;
+		api.add_function("get_layer", |ctx, r: &mut RenderSystem, _, name: String| {
+			rlua_serde::to_value(ctx, r.layers.iter().find(|l| l.name == name))
+		})?;
+		api.add_function("get_layers", |ctx, r: &mut RenderSystem, _, ()| {
+			rlua_serde::to_value(ctx, &r.layers)
+		})?;
+		api.add_function("move_to_layer", |_, r: &mut RenderSystem, members, (id, layer): (Entity, String)| {
+			if let Some(i) = members.slot(id) {
+				r.sprite_layers[i] = layer;
+			}
+			Ok(())
+		})?;
+		api.add_function("set_size", |_, r: &mut RenderSystem, members, (id, width, height): (Entity, u32, u32)| {
+			if let Some(i) = members.slot(id) {
+				r.frames[i].width = width;
+				r.frames[i].height = height;
+			}
+			Ok(())
+		})?;
+		api.add_function("set_animation", |_, r: &mut RenderSystem, members, (id, animation): (Entity, String)| {
+			if let Some(i) = members.slot(id) {
+				r.animations[i].animation = animation;
+			}
+			Ok(())
+		})?;
+		//sets the frame of whichever animation is currently playing
+		api.add_function("set_frame", |_, r: &mut RenderSystem, members, (id, frame): (Entity, f64)| {
+			if let Some(i) = members.slot(id) {
+				let AnimationComponent{animations, animation} = &mut r.animations[i];
+				if let Some(animation) = animations.get_mut(animation.as_str()) {
+					animation.current_frame = frame;
+				}
+			}
+			Ok(())
+		})?;
+		Ok(())
+	}
+	fn spawn<'lua>(&mut self, _: rlua::Context<'lua>, _: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let RenderInfo{sprite, animations, animation, x, y, width, height, z_index, rotation, layer} = rlua_serde::from_value(object).map_err(WorldError::parse)?;
+		self.sprites.push(sprite);
+		self.frames.push(Frame{x, y, width, height});
+		self.previous.push((x, y));
+		self.ordering.push(z_index);
+		self.sprite_layers.push(layer);
+		self.animations.push(AnimationComponent{animations, animation});
+		self.rotations.push(rotation);
+		self.scales.push((1.0, 1.0));
+		Ok(())
+	}
+	fn despawn(&mut self, slot: usize) {
+		self.sprites.swap_remove(slot);
+		self.frames.swap_remove(slot);
+		self.previous.swap_remove(slot);
+		self.ordering.swap_remove(slot);
+		self.sprite_layers.swap_remove(slot);
+		self.rotations.swap_remove(slot);
+		self.scales.swap_remove(slot);
+		self.animations.swap_remove(slot);
+	}
+	fn tick(&mut self, _: &World, dt: f64) {
 		for a in &mut self.animations {
 			//this is needed bc they might not have animations at all
 			if let Some(a) = a.animations.get_mut(&a.animation) {
 				if a.last != 0 {
-					a.current_frame += a.speed;
+					a.current_frame += a.speed * dt;
 					//rendered frame index = floor(current_frame)
-					//if speed is 0.1
+					//if speed * dt is 0.1
 					//1.0-1.9 = frame 1; 2.0-2.9 = frame 2; etc.
 					//resets when no longer currently on last frame i.e. >= last + 1
 					//i.e. if last frame is 3 then reset > 3.9, or, >= 4.0
@@ -126,68 +287,136 @@ impl NativeSystem for RenderSystem {
 			}
 		}
 	}
-	fn get<'lua>(&self, ctx: rlua::Context<'lua>, entity: usize) -> rlua::Value<'lua> {
-		if let Some(sprite) = self.sprites.get(&entity) {
-			let Frame{x, y, width, height} = self.frames[&entity];
-			let z_index = self.ordering[&entity];
-			let rotation = self.rotations[&entity];
-			let i = self.entities[&entity];
-			let animation_component = &self.animations[i];
-			rlua_serde::to_value(ctx, RenderInfo{
-				sprite: sprite.to_string(), 
-				animations: animation_component.animations.clone(), 
-				animation: animation_component.animation.clone(),
-				x, y,
-				width,
-				height,
-				z_index,
-				rotation}).unwrap()
-		} else {
-			rlua::Value::Nil
+	//what other systems can link into, e.g. .link("Physics", "position", "position")
+	fn write_field(&mut self, slot: usize, field: &str, value: Field) {
+		match (field, value) {
+			("position", Field::Vector(x, y)) => self.set_position(slot, x, y),
+			("rotation", Field::Number(rotation)) => self.set_rotation(slot, rotation),
+			("scale", Field::Vector(x, y)) => self.scales[slot] = (x, y),
+			_ => {},
 		}
 	}
-	fn set<'lua>(&mut self, entity: usize, value: rlua::Value<'lua>) {
-		if self.sprites.contains_key(&entity) {
-			if let Ok(RenderInfo{sprite, animation, animations, x, y, width, height, z_index, rotation}) = rlua_serde::from_value(value) {
-				self.sprites.insert(entity, sprite);
-				self.frames.insert(entity, Frame{x, y, width, height});
-				self.ordering.insert(entity, z_index);
-				self.rotations.insert(entity, rotation);
-				self.animations[self.entities[&entity]] = AnimationComponent{animations, animation};
-			}
-		}
+	fn get<'lua>(&self, ctx: rlua::Context<'lua>, i: usize) -> Result<rlua::Value<'lua>, WorldError> {
+		let Frame{x, y, width, height} = self.frames[i];
+		let animation_component = &self.animations[i];
+		rlua_serde::to_value(ctx, RenderInfo{
+			sprite: self.sprites[i].to_string(), 
+			animations: animation_component.animations.clone(), 
+			animation: animation_component.animation.clone(),
+			x, y,
+			width,
+			height,
+			z_index: self.ordering[i],
+			layer: self.sprite_layers[i].clone(),
+			rotation: self.rotations[i]}).map_err(WorldError::parse)
+	}
+	fn set<'lua>(&mut self, _: rlua::Context<'lua>, i: usize, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let RenderInfo{sprite, animation, animations, x, y, width, height, z_index, rotation, layer} = rlua_serde::from_value(value).map_err(WorldError::parse)?;
+		self.sprites[i] = sprite;
+		self.frames[i] = Frame{x, y, width, height};
+		//a set is a teleport, don't smear it across the step
+		self.previous[i] = (x, y);
+		self.ordering[i] = z_index;
+		self.sprite_layers[i] = layer;
+		self.rotations[i] = rotation;
+		self.animations[i] = AnimationComponent{animations, animation};
+		Ok(())
 	}
-	fn save(&self) -> serde_json::Value {
-		serde_json::to_value(self).unwrap()
+	fn save(&self) -> Result<serde_json::Value, WorldError> {
+		serde_json::to_value(self).map_err(WorldError::parse)
+	}
+	fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError> {
+		*self = serde_json::from_value(state).map_err(WorldError::parse)?;
+		self.previous = self.frames.iter().map(|f| (f.x, f.y)).collect();
+		self.scales = vec![(1.0, 1.0); self.frames.len()];
+		self.sprite_layers.resize(self.frames.len(), main_layer());
+		Ok(())
+	}
+	fn slots(&self) -> Result<usize, WorldError> {
+		slot_count(&[
+			("sprites", self.sprites.len()),
+			("frames", self.frames.len()),
+			("ordering", self.ordering.len()),
+			("sprite_layers", self.sprite_layers.len()),
+			("rotations", self.rotations.len()),
+			("animations", self.animations.len()),
+		])
 	}
 }
 
 
 use crate::sdl_renderer::{SdlRenderer, Render};
-impl Render for RenderSystem {
-	fn render(&mut self, r: &mut SdlRenderer) {
+use crate::tilemap::TilemapSystem;
+
+//tilemaps go before sprites with the same z_index
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Draw {
+	Tilemap(usize),
+	Sprite(usize),
+}
+
+impl RenderSystem {
+	//sprites, and the tilemaps in with them on whatever layer they're on
+	pub fn render_with(&mut self, r: &mut SdlRenderer, tilemaps: Option<&TilemapSystem>) {
+		//every layer in use, by how it's drawn
+		let mut layers: Vec<Layer> = Vec::new();
+		let mut layer_index = |name: &str| match layers.iter().position(|l| l.name == name) {
+			Some(index) => index,
+			None => {
+				layers.push(self.layer(name));
+				layers.len() - 1
+			},
+		};
+		let layer_of: Vec<usize> = self.sprite_layers.iter().map(|name| layer_index(name)).collect();
+		let map_layers: Vec<usize> = tilemaps.map_or(Vec::new(), |t| t.maps.iter().map(|map| layer_index(&map.layer)).collect());
+		let views: Vec<View> = layers.iter().map(|l| l.view(&self.view, r.screen_width, r.screen_height)).collect();
+
+		//same z_index draws get grouped by atlas page, so there are fewer texture switches
 		let mut render_queue = Vec::new();
-		for (i, z) in &self.ordering {
-			render_queue.push((z, i));
+		for (i, z) in self.ordering.iter().enumerate() {
+			if let Some(sprite) = r.atlas.sprite(&self.sprites[i]) {
+				let layer = layer_of[i];
+				render_queue.push((layers[layer].order, layer, *z, sprite.page, Draw::Sprite(i)));
+			}
+		}
+		if let Some(tilemaps) = tilemaps {
+			for (i, (map, &layer)) in tilemaps.maps.iter().zip(&map_layers).enumerate() {
+				//page 0 so they still go first
+				render_queue.push((layers[layer].order, layer, map.z_index, 0, Draw::Tilemap(i)));
+			}
 		}
 		render_queue.sort_unstable();
-		for (_, i) in render_queue {
-
-			let tex = &r.textures[&self.sprites[i]];
-			let frame = &mut self.frames.get_mut(i).unwrap();
-			let mut src_rect = None;
-			if let Some(&i) = self.entities.get(i) {
-				let current_animation = self.animations[i].animation.clone();
-				let mut animation = self.animations.get_mut(i).unwrap().animations.get_mut(&current_animation).unwrap();
+		let mut current_layer = None;
+		for (_, layer, _, _, draw) in render_queue {
+			let view = views[layer];
+			if current_layer != Some(layer) {
+				//screen space layers aren't kept inside the camera's viewport
+				r.canvas.set_clip_rect(Rect::new(view.viewport.x as i32, view.viewport.y as i32, view.viewport.width.max(1.0) as u32, view.viewport.height.max(1.0) as u32));
+				current_layer = Some(layer);
+			}
+			let i = match (draw, tilemaps) {
+				(Draw::Sprite(i), _) => i,
+				(Draw::Tilemap(i), Some(tilemaps)) => {
+					tilemaps.draw(i, r, &view);
+					continue;
+				},
+				(Draw::Tilemap(_), None) => continue,
+			};
+
+			let (tex, sprite_rect) = r.atlas.get(&self.sprites[i]).unwrap();
+			let frame = &mut self.frames[i];
+			let mut src_rect = sprite_rect;
+			let AnimationComponent{animations, animation} = &mut self.animations[i];
+			if let Some(animation) = animations.get_mut(animation.as_str()) {
 				if animation.last == 0 {
 					//if animation end is not defined (=0), set it to the last frame in the row
-					animation.last = tex.query().width / animation.frame_width;
+					animation.last = sprite_rect.width() / animation.frame_width;
 				}
 				if animation.frame_height == 0 {
-					animation.frame_height = tex.query().height;
+					animation.frame_height = sprite_rect.height();
 				}
 				if animation.frame_width == 0 {
-					animation.frame_width = tex.query().width;
+					animation.frame_width = sprite_rect.width();
 				}
 
 				if frame.width == 0 {
@@ -200,23 +429,39 @@ impl Render for RenderSystem {
 				//width > frame_width > texture.width
 				//height > frame_height > texture.height
 
-				src_rect = Some(Rect::new(
-					((animation.current_frame - 1.0).floor() * animation.frame_width as f64) as i32, 
-					animation.row as i32,
+				//frames are relative to wherever the sprite is in the atlas
+				src_rect = Rect::new(
+					sprite_rect.x() + ((animation.current_frame - 1.0).floor() * animation.frame_width as f64) as i32, 
+					sprite_rect.y() + animation.row as i32,
 					animation.frame_width, 
-					animation.frame_height));
+					animation.frame_height);
 			}
 
+			let (previous_x, previous_y) = self.previous[i];
+			let x = previous_x + (frame.x - previous_x) * self.alpha;
+			let y = previous_y + (frame.y - previous_y) * self.alpha;
 			//TODO: maybe let the user select between these two with a 'centered' boolean
-			let draw_rect = Rect::new(frame.x as i32 - frame.width as i32 / 2 - self.camera_x, frame.y as i32 - frame.height as i32 / 2 - self.camera_y, frame.width, frame.height);
+			let (scale_x, scale_y) = self.scales[i];
+			let (width, height) = (frame.width as f64 * scale_x.abs() * view.zoom, frame.height as f64 * scale_y.abs() * view.zoom);
+			let (x, y) = view.to_screen(x, y);
+			let draw_rect = Rect::new((x - width / 2.0) as i32, (y - height / 2.0) as i32, width as u32, height as u32);
 			//let draw_rect = Rect::new(frame.x as i32, frame.y as i32, frame.width, frame.height);
 
-			if draw_rect.x + draw_rect.w > 0 
-			&& draw_rect.x < r.screen_width as i32
-			&& draw_rect.y + draw_rect.h > 0
-			&& draw_rect.y < r.screen_height as i32 {
-				let _ = r.canvas.copy_ex(tex, src_rect, draw_rect, self.rotations[i], None, false, false);
+			//however it's rotated, it stays inside this
+			let reach = (width * width + height * height).sqrt() / 2.0;
+			if x + reach > view.viewport.x
+			&& x - reach < view.viewport.x + view.viewport.width
+			&& y + reach > view.viewport.y
+			&& y - reach < view.viewport.y + view.viewport.height {
+				let _ = r.canvas.copy_ex(tex, src_rect, draw_rect, self.rotations[i] - view.rotation, None, scale_x < 0.0, scale_y < 0.0);
 			}
 		}
+		r.canvas.set_clip_rect(None);
+	}
+}
+
+impl Render for RenderSystem {
+	fn render(&mut self, r: &mut SdlRenderer) {
+		self.render_with(r, None);
 	}
 }
@@ -0,0 +1,342 @@
+use crate::error::WorldError;
+use std::collections::HashMap;
+
+//systems run stage by stage, and inside a stage in an order that satisfies every before/after
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Stage {
+	PreUpdate,
+	#[default]
+	Update,
+	PostUpdate,
+	RenderPrep,
+}
+
+impl std::str::FromStr for Stage {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Stage, String> {
+		match s {
+			"pre_update" => Ok(Stage::PreUpdate),
+			"update" => Ok(Stage::Update),
+			"post_update" => Ok(Stage::PostUpdate),
+			"render_prep" => Ok(Stage::RenderPrep),
+			_ => Err(format!("unknown stage '{}' (expected pre_update, update, post_update or render_prep)", s)),
+		}
+	}
+}
+
+//other systems a native system touches from its tick, through the &World it's given
+//(it always gets to write itself)
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+	pub reads: Vec<String>,
+	pub writes: Vec<String>,
+}
+
+impl Access {
+	fn writes<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+		self.writes.iter().map(String::as_str).chain(std::iter::once(name))
+	}
+	fn touches<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+		self.reads.iter().map(String::as_str).chain(self.writes(name))
+	}
+	//two systems conflict if either one writes something the other one touches
+	fn conflicts(&self, name: &str, other: &Access, other_name: &str) -> bool {
+		self.writes(name).any(|w| other.touches(other_name).any(|t| t == w))
+			|| other.writes(other_name).any(|w| self.touches(name).any(|t| t == w))
+	}
+}
+
+//right before the system ticks, the World copies `field` out of `source` into `into`,
+//for every entity that belongs to both (see NativeSystem::read_field/write_field)
+#[derive(Debug, Clone)]
+pub struct Link {
+	pub source: String,
+	pub field: String,
+	pub into: String,
+}
+
+//SystemOptions::new().stage(Stage::PostUpdate).after("Physics").reads("Physics")
+#[derive(Debug, Clone, Default)]
+pub struct SystemOptions {
+	pub stage: Stage,
+	pub before: Vec<String>,
+	pub after: Vec<String>,
+	//None = never run alongside anything else, only native systems can be Some
+	pub access: Option<Access>,
+	//applied in order, so a later link into the same field wins
+	pub links: Vec<Link>,
+}
+
+
+#[allow(unused)]
+impl SystemOptions {
+	pub fn new() -> SystemOptions {
+		SystemOptions::default()
+	}
+	pub fn stage(mut self, stage: Stage) -> SystemOptions {
+		self.stage = stage;
+		self
+	}
+	pub fn before(mut self, name: &str) -> SystemOptions {
+		self.before.push(name.to_string());
+		self
+	}
+	pub fn after(mut self, name: &str) -> SystemOptions {
+		self.after.push(name.to_string());
+		self
+	}
+	//declares that tick only touches its own system, so it can run on the worker pool
+	pub fn parallel(mut self) -> SystemOptions {
+		self.access.get_or_insert_with(Access::default);
+		self
+	}
+	pub fn reads(mut self, name: &str) -> SystemOptions {
+		self.access.get_or_insert_with(Access::default).reads.push(name.to_string());
+		self
+	}
+	pub fn writes(mut self, name: &str) -> SystemOptions {
+		self.access.get_or_insert_with(Access::default).writes.push(name.to_string());
+		self
+	}
+	//.link("Physics", "angle", "rotation"): copy Physics' angle into this system's rotation before every tick
+	pub fn link(mut self, source: &str, field: &str, into: &str) -> SystemOptions {
+		self.links.push(Link{source: source.to_string(), field: field.to_string(), into: into.to_string()});
+		self
+	}
+	//what it touches, counting the systems it has links from
+	fn effective_access(&self) -> Option<Access> {
+		self.access.clone().map(|mut access| {
+			access.reads.extend(self.links.iter().map(|l| l.source.clone()));
+			access
+		})
+	}
+}
+
+//{stage = "post_update", before = "Render", after = {"Physics", "Input"}}
+impl<'lua> rlua::FromLua<'lua> for SystemOptions {
+	fn from_lua(value: rlua::Value<'lua>, _: rlua::Context<'lua>) -> rlua::Result<SystemOptions> {
+		let table = match value {
+			rlua::Value::Nil => return Ok(SystemOptions::default()),
+			rlua::Value::Table(table) => table,
+			_ => return Err(rlua::Error::FromLuaConversionError{from: "value", to: "SystemOptions", message: Some("expected a table".to_string())}),
+		};
+		let names = |key: &str| -> rlua::Result<Vec<String>> {
+			match table.get::<_, rlua::Value>(key)? {
+				rlua::Value::Nil => Ok(Vec::new()),
+				rlua::Value::String(s) => Ok(vec![s.to_str()?.to_string()]),
+				rlua::Value::Table(t) => t.sequence_values::<String>().collect(),
+				_ => Err(rlua::Error::FromLuaConversionError{from: "value", to: "SystemOptions", message: Some(format!("'{}' should be a name or a list of names", key))}),
+			}
+		};
+		let stage = match table.get::<_, Option<String>>("stage")? {
+			Some(stage) => stage.parse().map_err(|e| rlua::Error::FromLuaConversionError{from: "string", to: "Stage", message: Some(e)})?,
+			None => Stage::default(),
+		};
+		Ok(SystemOptions{stage, before: names("before")?, after: names("after")?, access: None, links: Vec::new()})
+	}
+}
+
+#[derive(Debug, Default)]
+pub struct Schedule {
+	//registration order, used to break ties so the order never changes between runs
+	systems: Vec<(String, SystemOptions)>,
+	order: Vec<String>,
+}
+
+impl Schedule {
+	pub fn new() -> Schedule {
+		Schedule::default()
+	}
+	pub fn order(&self) -> &[String] {
+		&self.order
+	}
+	//re-adding a name replaces it; nothing changes if the new constraints can't be satisfied
+	pub fn add(&mut self, name: &str, options: SystemOptions) -> Result<(), WorldError> {
+		let mut systems = self.systems.clone();
+		systems.retain(|(n, _)| n != name);
+		systems.push((name.to_string(), options));
+		self.order = Schedule::sort(&systems)?;
+		self.systems = systems;
+		Ok(())
+	}
+	//splits the order into batches that can each run at the same time
+	//a batch is a run of neighbouring systems in the same stage that declared non-conflicting access
+	//and aren't ordered against each other; everything else gets a batch of its own
+	pub fn batches(&self) -> Vec<Vec<String>> {
+		let options: HashMap<&str, &SystemOptions> = self.systems.iter().map(|(n, o)| (n.as_str(), o)).collect();
+		let access: HashMap<&str, Option<Access>> = self.systems.iter().map(|(n, o)| (n.as_str(), o.effective_access())).collect();
+		let mut batches: Vec<Vec<String>> = Vec::new();
+		let mut open = false;
+		for name in &self.order {
+			let current = options[name.as_str()];
+			let fits = open && access[name.as_str()].as_ref().is_some_and(|current_access| {
+				batches.last().unwrap().iter().all(|other| {
+					let o = options[other.as_str()];
+					o.stage == current.stage
+						&& !access[other.as_str()].as_ref().unwrap().conflicts(other, current_access, name)
+						&& !o.before.contains(name) && !o.after.contains(name)
+						&& !current.before.contains(other) && !current.after.contains(other)
+				})
+			});
+			if fits {
+				batches.last_mut().unwrap().push(name.clone());
+			} else {
+				batches.push(vec![name.clone()]);
+			}
+			open = current.access.is_some();
+		}
+		batches
+	}
+	pub fn links(&self, name: &str) -> &[Link] {
+		self.systems.iter().find(|(n, _)| n == name).map_or(&[], |(_, o)| &o.links[..])
+	}
+	pub fn remove(&mut self, name: &str) {
+		self.systems.retain(|(n, _)| n != name);
+		self.order.retain(|n| n != name);
+	}
+
+	//Kahn's algorithm, always picking the earliest stage and then the earliest registered system that's ready
+	//constraints on systems that aren't registered (yet) are ignored
+	fn sort(systems: &[(String, SystemOptions)]) -> Result<Vec<String>, WorldError> {
+		let index: HashMap<&str, usize> = systems.iter().enumerate().map(|(i, (n, _))| (n.as_str(), i)).collect();
+		let mut edges = vec![Vec::new(); systems.len()];
+		let mut incoming = vec![0; systems.len()];
+		for (i, (_, options)) in systems.iter().enumerate() {
+			let before = options.before.iter().filter_map(|n| index.get(n.as_str())).map(|&j| (i, j));
+			let after = options.after.iter().filter_map(|n| index.get(n.as_str())).map(|&j| (j, i));
+			for (from, to) in before.chain(after) {
+				//the stages already put `to` first, so this can never be satisfied
+				if systems[from].1.stage > systems[to].1.stage {
+					return Err(WorldError::ScheduleCycle(vec![systems[from].0.clone(), systems[to].0.clone(), systems[from].0.clone()]));
+				}
+				edges[from].push(to);
+				incoming[to] += 1;
+			}
+		}
+		let mut order = Vec::new();
+		let mut done = vec![false; systems.len()];
+		while order.len() < systems.len() {
+			let next = (0..systems.len())
+				.filter(|&i| !done[i] && incoming[i] == 0)
+				.min_by_key(|&i| (systems[i].1.stage, i));
+			match next {
+				Some(i) => {
+					done[i] = true;
+					for &j in &edges[i] {
+						incoming[j] -= 1;
+					}
+					order.push(systems[i].0.clone());
+				},
+				None => {
+					//everything left is waiting on something else that's left, so walking backwards has to loop
+					let mut path = vec![(0..systems.len()).find(|&i| !done[i]).unwrap()];
+					loop {
+						let current = *path.last().unwrap();
+						let previous = (0..systems.len()).find(|&i| !done[i] && edges[i].contains(&current)).unwrap();
+						if let Some(start) = path.iter().position(|&i| i == previous) {
+							let mut cycle: Vec<String> = path[start..].iter().rev().map(|&i| systems[i].0.clone()).collect();
+							cycle.push(cycle[0].clone());
+							return Err(WorldError::ScheduleCycle(cycle));
+						}
+						path.push(previous);
+					}
+				}
+			}
+		}
+		Ok(order)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn cycle(result: Result<(), WorldError>) -> Vec<String> {
+		match result {
+			Err(WorldError::ScheduleCycle(names)) => names,
+			result => panic!("expected a cycle, got {:?}", result),
+		}
+	}
+
+	#[test]
+	fn stages_first_then_registration_order() {
+		let mut schedule = Schedule::new();
+		schedule.add("Render", SystemOptions::new().stage(Stage::RenderPrep)).unwrap();
+		schedule.add("Physics", SystemOptions::new()).unwrap();
+		schedule.add("Input", SystemOptions::new().stage(Stage::PreUpdate)).unwrap();
+		schedule.add("Collision", SystemOptions::new()).unwrap();
+		schedule.add("Transform", SystemOptions::new().stage(Stage::PostUpdate)).unwrap();
+		assert_eq!(schedule.order(), &["Input", "Physics", "Collision", "Transform", "Render"]);
+		//the same registrations always come out the same way
+		let again = schedule.order().to_vec();
+		schedule.add("Physics", SystemOptions::new()).unwrap();
+		assert_eq!(schedule.order(), &["Input", "Collision", "Physics", "Transform", "Render"]);
+		schedule.add("Collision", SystemOptions::new()).unwrap();
+		assert_eq!(schedule.order(), &again[..]);
+	}
+
+	#[test]
+	fn before_and_after_beat_registration_order() {
+		let mut schedule = Schedule::new();
+		//Tilemap isn't registered yet, so this is ignored until it is
+		schedule.add("Collision", SystemOptions::new().after("Physics").after("Tilemap")).unwrap();
+		schedule.add("Physics", SystemOptions::new()).unwrap();
+		schedule.add("Tilemap", SystemOptions::new()).unwrap();
+		schedule.add("Ai", SystemOptions::new().before("Physics")).unwrap();
+		//Tilemap was registered before Ai and was ready just as soon
+		assert_eq!(schedule.order(), &["Tilemap", "Ai", "Physics", "Collision"]);
+	}
+
+	#[test]
+	fn two_cycle() {
+		let mut schedule = Schedule::new();
+		schedule.add("A", SystemOptions::new().after("B")).unwrap();
+		assert_eq!(cycle(schedule.add("B", SystemOptions::new().after("A"))), vec!["B", "A", "B"]);
+		//nothing changed
+		assert_eq!(schedule.order(), &["A"]);
+	}
+
+	#[test]
+	fn three_cycle() {
+		let mut schedule = Schedule::new();
+		schedule.add("A", SystemOptions::new().before("B")).unwrap();
+		schedule.add("B", SystemOptions::new().before("C")).unwrap();
+		assert_eq!(cycle(schedule.add("C", SystemOptions::new().before("A"))), vec!["B", "C", "A", "B"]);
+		assert_eq!(schedule.order(), &["A", "B"]);
+	}
+
+	#[test]
+	fn constraint_against_the_stages() {
+		let mut schedule = Schedule::new();
+		schedule.add("Input", SystemOptions::new().stage(Stage::PreUpdate)).unwrap();
+		assert_eq!(cycle(schedule.add("Physics", SystemOptions::new().before("Input"))), vec!["Physics", "Input", "Physics"]);
+	}
+
+	#[test]
+	fn writers_of_the_same_thing_get_separate_batches() {
+		let mut schedule = Schedule::new();
+		schedule.add("Collision", SystemOptions::new().writes("Physics")).unwrap();
+		schedule.add("Transform", SystemOptions::new().writes("Physics")).unwrap();
+		schedule.add("Tilemap", SystemOptions::new().parallel()).unwrap();
+		schedule.add("Render", SystemOptions::new().parallel().stage(Stage::RenderPrep)).unwrap();
+		schedule.add("Text", SystemOptions::new().parallel().stage(Stage::RenderPrep)).unwrap();
+		//Lua systems never declare access, so they're always on their own
+		schedule.add("Inventory", SystemOptions::new().stage(Stage::RenderPrep)).unwrap();
+		assert_eq!(schedule.batches(), vec![
+			vec!["Collision".to_string()],
+			vec!["Transform".to_string(), "Tilemap".to_string()],
+			vec!["Render".to_string(), "Text".to_string()],
+			vec!["Inventory".to_string()],
+		]);
+	}
+
+	#[test]
+	fn ordered_or_linked_systems_dont_share_a_batch() {
+		let mut schedule = Schedule::new();
+		schedule.add("Physics", SystemOptions::new().parallel()).unwrap();
+		schedule.add("Collision", SystemOptions::new().parallel().after("Physics")).unwrap();
+		//a link reads its source, which conflicts with the source writing itself
+		schedule.add("Render", SystemOptions::new().parallel().link("Collision", "position", "position")).unwrap();
+		assert_eq!(schedule.batches().len(), 3);
+	}
+}
@@ -1,11 +1,12 @@
 use sdl2::{Sdl, VideoSubsystem};
 use sdl2::video::{Window, WindowContext};
-use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::render::{Canvas, TextureCreator, BlendMode};
 use sdl2::surface::Surface;
-use sdl2::rwops::RWops;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use serde::Deserialize;
 
-use std::collections::HashMap;
+use crate::atlas::{self, Atlas};
+use crate::font::{BitmapFont, Fonts, FontStyle, Placement};
 
 pub struct SdlRenderer {
 	pub screen_width: u32,
@@ -13,11 +14,48 @@ pub struct SdlRenderer {
 	pub video: VideoSubsystem,
 	pub canvas: Canvas<Window>,
 	pub texture_creator: TextureCreator<WindowContext>,
-	pub textures: HashMap<String, Texture>,
+	pub atlas: Atlas,
+	pub fonts: Fonts,
+	//the last thing that went wrong drawing text, for the console to pick up after the frame
+	pub text_error: Option<String>,
+}
+
+//per-texture settings, from resources/textures.json: {"player_main": {"color_key": [255, 0, 255]}}
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TextureOptions {
+	//pixels this color become transparent, for images without an alpha channel
+	#[serde(default)]
+	pub color_key: Option<(u8, u8, u8)>,
+}
+
+//PNG, GIF (just the first frame), TGA or BMP, going by the extension on path
+pub fn load_image(path: &str, bytes: &[u8], options: &TextureOptions) -> Result<Surface<'static>, String> {
+	let format = image::ImageFormat::from_path(path).map_err(|e| format!("{}: {}", path, e))?;
+	let mut pixels = image::load_from_memory_with_format(bytes, format).map_err(|e| format!("{}: {}", path, e))?.to_rgba();
+	if let Some((r, g, b)) = options.color_key {
+		for pixel in pixels.pixels_mut().filter(|p| p[0] == r && p[1] == g && p[2] == b) {
+			pixel[3] = 0;
+		}
+	}
+	let (width, height) = pixels.dimensions();
+	if width == 0 || height == 0 {
+		return Err(format!("{}: image is empty", path));
+	}
+	let mut surface = Surface::new(width, height, PixelFormatEnum::RGBA32)?;
+	let pitch = surface.pitch() as usize;
+	let row = width as usize * 4;
+	surface.with_lock_mut(|data| {
+		for (y, source) in pixels.chunks(row).enumerate() {
+			data[y * pitch..y * pitch + row].copy_from_slice(source);
+		}
+	});
+	//gets copied onto the atlas as is, blending it onto the empty page would darken anything half transparent
+	surface.set_blend_mode(BlendMode::None)?;
+	Ok(surface)
 }
 
 impl SdlRenderer {
-	pub fn new(sdl_context: &Sdl, name: &str, width: u32, height: u32) -> Result<Self, Box<std::error::Error>> {
+	pub fn new(sdl_context: &Sdl, name: &str, width: u32, height: u32) -> Result<Self, Box<dyn std::error::Error>> {
 		let video = sdl_context.video()?;
 		video.text_input().stop();
 		let window = video.window(name, width, height)
@@ -35,15 +73,49 @@ impl SdlRenderer {
 		canvas.set_logical_size(640, 400)?;
 		canvas.set_blend_mode(sdl2::render::BlendMode::Blend);
     	let texture_creator = canvas.texture_creator();
-		Ok(SdlRenderer{screen_width: width, screen_height: height, video, canvas, texture_creator, textures: HashMap::new()})
+		Ok(SdlRenderer{screen_width: width, screen_height: height, video, canvas, texture_creator, atlas: Atlas::new(), fonts: Fonts::new(), text_error: None})
 
 	}
 
-	pub fn insert_texture(&mut self, name: String, tex: &[u8]) {
-		let mut surface = Surface::load_bmp_rw(&mut RWops::from_bytes(tex).unwrap()).unwrap();
-		surface.set_color_key(true, Color::RGB(255, 0, 255)).unwrap();
-		let tex = self.texture_creator.create_texture_from_surface(&surface).unwrap();
-		self.textures.insert(name, tex);
+	//replaces whatever sprites were loaded before
+	pub fn load_sprites(&mut self, images: &[(String, Surface<'static>)]) -> Result<(), String> {
+		let info = self.canvas.info();
+		//0 means there's no limit
+		let limit = match info.max_texture_width.min(info.max_texture_height) {
+			0 => atlas::PAGE_SIZE,
+			limit => limit.min(atlas::PAGE_SIZE),
+		};
+		self.atlas = Atlas::build(&self.texture_creator, images, limit)?;
+		Ok(())
+	}
+
+	//bitmap fonts only have ASCII
+	pub fn draw_text(&mut self, font: FontStyle, string: &str, at: Placement) {
+		match self.fonts.draw(&mut self.canvas, &self.texture_creator, font, string, at) {
+			Ok(true) => return,
+			Ok(false) => {},
+			//the glyph cache can't fail in a way that'd be worth stopping the frame for
+			Err(e) => {
+				self.text_error = Some(e);
+				return;
+			},
+		}
+		let Placement{x, y, color} = at;
+		if let Some((texture, rect)) = self.atlas.get_mut(font.name) {
+			texture.set_color_mod(color.r, color.g, color.b);
+			texture.set_alpha_mod(color.a);
+			BitmapFont{texture, rect, char_width: font.char_width, char_height: font.char_height}.draw(&mut self.canvas, string, x, y);
+			//the page is shared with every other sprite
+			let (texture, _) = self.atlas.get_mut(font.name).unwrap();
+			texture.set_color_mod(255, 255, 255);
+			texture.set_alpha_mod(255);
+		}
+	}
+	pub fn text_width(&self, font: FontStyle, string: &str) -> u32 {
+		self.fonts.width(font.name, font.size, string).unwrap_or(string.chars().count() as u32 * font.char_width)
+	}
+	pub fn line_height(&self, font: FontStyle) -> u32 {
+		self.fonts.line_height(font.name, font.size).unwrap_or(font.char_height)
 	}
 
 	pub fn clear(&mut self, r: u8, g: u8, b: u8) {
@@ -58,17 +130,6 @@ impl SdlRenderer {
 	pub fn present(&mut self) {
 		self.canvas.present();
 	}
-
-    //TODO: text rendering system (leave game logic to lua, only have basic text/textbox drawing)
-    /* 
-    'text' part of Render:
-		- 'font' aliased to 'sprite', draws sprite as font characters
-		- 'width' and 'height' are now the width and height of text box, or unbounded if 0
-		- 'animation_speed' aliased to 'text_speed', controls typing speed
-		- 'text' includes just an ascii string with which to render
-		- maybe include ttf support for UTF-8
-		- 'border_color', 'border_width', and 'background_color' also settable, default to white on black 
-	*/
 }
 
 
@@ -1,7 +1,7 @@
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use sdl2::render::{Canvas, Texture};
-use sdl2::video::Window;
+use crate::error::describe_lua_error;
+use crate::font::{FontStyle, Placement};
 
 //this one's not a NativeSystem it's just a thing
 //this could probably be more sophisticated but it works?
@@ -12,13 +12,15 @@ pub struct Terminal {
 	height: u32, //max height
 	on: bool,
 	h: u32,		//actual height within animation
+	//a TTF drawn char_height pixels tall, or a bitmap font sprite
+	font: String,
 	char_width: u32,
 	char_height: u32,
 }
 
 impl Terminal {
-	pub fn new(height: u32, char_width: u32, char_height: u32) -> Terminal {
-		Terminal{commandline: String::new(), commands: Vec::new(), outputs: Vec::new(), height, char_width, char_height, on: false, h: 0}
+	pub fn new(height: u32, font: &str, char_width: u32, char_height: u32) -> Terminal {
+		Terminal{commandline: String::new(), commands: Vec::new(), outputs: Vec::new(), height, font: font.to_string(), char_width, char_height, on: false, h: 0}
 	}
 	pub fn toggle(&mut self) {
 		self.on = !self.on;
@@ -33,9 +35,19 @@ impl Terminal {
 		self.commandline += commandline;
 	}
 	pub fn backspace(&mut self) {
-		if self.commandline.len() > 0 {
-			self.commandline = String::from(&self.commandline[0..self.commandline.len()-1]);
+		self.commandline.pop();
+	}
+	//errors from scripts end up here instead of taking the whole game down
+	//the full message (tracebacks and all) goes to stderr, the terminal only has room for the first line
+	pub fn log(&mut self, message: &str) {
+		eprintln!("{}", message);
+		let line = message.lines().next().unwrap_or("").to_string();
+		//a broken tick fails every frame, don't flood the console with it
+		if self.outputs.first() == Some(&line) {
+			return;
 		}
+		self.commands.insert(0, String::from("error:"));
+		self.outputs.insert(0, line);
 	}
 	pub fn process_commandline(&mut self, ctx: rlua::Context) {
 		self.process_command(ctx, self.commandline.clone());
@@ -45,38 +57,29 @@ impl Terminal {
 		self.commands.insert(0, command);
 		let output = ctx.load(&self.commands[0]).eval::<rlua::Value>();
 		if let Err(e) = output {
-			self.outputs.insert(0, e.to_string());
+			self.outputs.insert(0, describe_lua_error(&e).lines().next().unwrap_or("").to_string());
 		} else if let Ok(v) = output {
 			match v {
 				rlua::Value::Nil => self.outputs.insert(0, "Nil".to_string()),
 				rlua::Value::Boolean(v) => self.outputs.insert(0, format!("{}", v)),
 				rlua::Value::Integer(v) => self.outputs.insert(0, format!("{}", v)),
 				rlua::Value::Number(v) => self.outputs.insert(0, format!("{}", v)),
-				rlua::Value::String(v) => self.outputs.insert(0, format!("{}", v.to_str().unwrap())),
+				rlua::Value::String(v) => self.outputs.insert(0, v.to_str().unwrap().to_string()),
 				v => self.outputs.insert(0, format!("{:?}", v)),
 				
 			}
 		}
 		println!("output: {}", self.outputs[0]);
 	}
-	pub fn draw_string(&self, canvas: &mut Canvas<Window>, font: &Texture, string: &str, line: u32) {
-		canvas.set_draw_color(Color::RGBA(255, 255, 255, 255));
-		for (i, c) in string.chars().enumerate() {
-			let position = c as i32 - 32;
-			let charx = (position % (font.query().width / self.char_width) as i32) * self.char_width as i32;
-			let chary = (position / (font.query().width / self.char_width) as i32) * self.char_height as i32;
-			let _ = canvas.copy(font, 
-				Rect::new(charx, chary, self.char_width, self.char_height),//Rect::new((position * self.char_width as i32) % font.query().width as i32, font.query().height as i32 / position, self.char_width, self.char_height),
-				Rect::new(i as i32 * self.char_width as i32, line as i32 * self.char_height as i32, self.char_width, self.char_height)).unwrap();
-		}
-
+	pub fn draw_string(&self, r: &mut SdlRenderer, string: &str, line: u32) {
+		let font = FontStyle{name: &self.font, size: self.char_height, char_width: self.char_width, char_height: self.char_height};
+		r.draw_text(font, string, Placement{x: 0, y: (line * self.char_height) as i32, color: Color::RGBA(255, 255, 255, 255)});
 	}
 }
 
 use crate::sdl_renderer::{Render, SdlRenderer};
 impl Render for Terminal {
 	fn render(&mut self, r: &mut SdlRenderer) {
-		let font = &r.textures["font-oldschool"];
 		if !self.on && self.h > 0 {
 			self.h -= 20;
 		}
@@ -90,16 +93,16 @@ impl Render for Terminal {
 			//draw text
 			let line_height = self.height / self.char_height - 3;
 			let _ = r.canvas.fill_rect(Rect::new(0, (line_height as i32 + 2) * self.char_height as i32, r.screen_width, self.char_height));
-			self.draw_string(&mut r.canvas, font, &self.commandline, line_height + 2);
+			self.draw_string(r, &self.commandline, line_height + 2);
 			for (line, command) in self.commands.iter().enumerate() {
-				if (line as u32 + 3) * self.char_height as u32 > self.height / 2 {
+				if (line as u32 + 3) * self.char_height > self.height / 2 {
 					break;
 				}
-				self.draw_string(&mut r.canvas, font, &command, line_height - (line as u32 * 2 + 1));
-				self.draw_string(&mut r.canvas, font, &self.outputs[line], line_height - (line as u32 * 2));
+				self.draw_string(r, command, line_height - (line as u32 * 2 + 1));
+				self.draw_string(r, &self.outputs[line], line_height - (line as u32 * 2));
 			}
 
-	    	self.draw_string(&mut r.canvas, font, "lua dev console 0.2.8", 0);
+	    	self.draw_string(r, "lua dev console 0.2.8", 0);
 	    }
 
 	}
@@ -0,0 +1,326 @@
+use serde::{Serialize, Deserialize};
+use crate::world::{World, NativeSystem, LuaApi, Field};
+use crate::entity::Entity;
+use crate::error::WorldError;
+
+use crate::font::{FontStyle, Placement};
+use crate::camera::View;
+
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+//breaks text into lines that measure no wider than max_width, at spaces where it can; 0 only breaks at newlines
+//the space a line got broken at stays on the end of it, so every character but the newlines is on some line
+pub fn wrap<F: Fn(&str) -> u32>(text: &str, max_width: u32, measure: F) -> Vec<String> {
+	let mut lines = Vec::new();
+	for paragraph in text.split('\n') {
+		if max_width == 0 {
+			lines.push(paragraph.to_string());
+			continue;
+		}
+		let mut line = String::new();
+		for word in paragraph.split_inclusive(' ') {
+			if measure(format!("{}{}", line, word).trim_end()) <= max_width {
+				line.push_str(word);
+				continue;
+			}
+			if !line.is_empty() {
+				lines.push(std::mem::take(&mut line));
+			}
+			//words that don't fit on a line of their own get cut up
+			for c in word.chars() {
+				line.push(c);
+				if line.chars().count() > 1 && measure(line.trim_end()) > max_width {
+					line.pop();
+					lines.push(std::mem::replace(&mut line, c.to_string()));
+				}
+			}
+		}
+		lines.push(line);
+	}
+	lines
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+	#[default]
+	Left,
+	Center,
+	Right,
+}
+
+fn full() -> u8 {
+	255
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Rgba {
+	pub r: u8,
+	pub g: u8,
+	pub b: u8,
+	#[serde(default = "full")]
+	pub a: u8,
+}
+
+impl From<Rgba> for Color {
+	fn from(c: Rgba) -> Color {
+		Color::RGBA(c.r, c.g, c.b, c.a)
+	}
+}
+
+fn white() -> Rgba {
+	Rgba{r: 255, g: 255, b: 255, a: 255}
+}
+fn font() -> String {
+	"font-oldschool".to_string()
+}
+fn size() -> u32 {
+	12
+}
+fn char_width() -> u32 {
+	7
+}
+fn char_height() -> u32 {
+	9
+}
+
+//what Lua sees, e.g. Text = {text = "hello there", width = 140, align = "center", text_speed = 20}
+//font is a TTF drawn at size pixels, or else a bitmap font sprite with char_width x char_height cells
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextBox {
+	#[serde(default)]
+	pub text: String,
+	#[serde(default = "font")]
+	pub font: String,
+	#[serde(default = "size")]
+	pub size: u32,
+	#[serde(default = "char_width")]
+	pub char_width: u32,
+	#[serde(default = "char_height")]
+	pub char_height: u32,
+	//top left corner of the box
+	#[serde(default)]
+	pub x: f64,
+	#[serde(default)]
+	pub y: f64,
+	//0 = as wide as the text / as tall as all of its lines
+	#[serde(default)]
+	pub width: u32,
+	#[serde(default)]
+	pub height: u32,
+	#[serde(default)]
+	pub align: Align,
+	//characters per second for the typewriter effect, 0 = all at once
+	#[serde(default)]
+	pub text_speed: f64,
+	//how many characters are showing so far
+	#[serde(default)]
+	pub revealed: f64,
+	#[serde(default = "white")]
+	pub color: Rgba,
+	#[serde(default)]
+	pub background_color: Option<Rgba>,
+	#[serde(default = "white")]
+	pub border_color: Rgba,
+	#[serde(default)]
+	pub border_width: u32,
+	//x, y are screen pixels and the camera doesn't move it, like Render's hud layer
+	#[serde(default)]
+	pub screen_space: bool,
+}
+
+impl TextBox {
+	fn style(&self) -> FontStyle<'_> {
+		FontStyle{name: &self.font, size: self.size, char_width: self.char_width, char_height: self.char_height}
+	}
+	//newlines take no time to type out
+	fn length(&self) -> usize {
+		self.text.chars().filter(|&c| c != '\n').count()
+	}
+	fn done(&self) -> bool {
+		self.revealed >= self.length() as f64
+	}
+}
+
+//text boxes, with word wrap, alignment and a typewriter reveal
+//drawn on top of every sprite; position can be linked like Render's
+//boxes move with the camera, but keep their size and stay upright however it's zoomed or turned
+//indexed by slot, see World::read_members("Text")
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TextSystem {
+	boxes: Vec<TextBox>,
+
+	//the active camera's, set every frame before rendering
+	#[serde(skip)]
+	pub view: View,
+}
+
+impl NativeSystem for TextSystem {
+	fn new() -> TextSystem {
+		TextSystem{boxes: Vec::new(), view: View::default()}
+	}
+	fn lua_api(&self, api: &LuaApi) -> rlua::Result<()> {
+		//starts the typewriter over
+		api.add_function("set_text", |_, t: &mut TextSystem, members, (id, text): (Entity, String)| {
+			if let Some(i) = members.slot(id) {
+				t.boxes[i].text = text;
+				t.boxes[i].revealed = 0.0;
+			}
+			Ok(())
+		})?;
+		api.add_function("get_text", |_, t: &mut TextSystem, members, id: Entity| {
+			Ok(members.slot(id).map(|i| t.boxes[i].text.clone()))
+		})?;
+		//shows the rest of the text right away, e.g. when the player presses a button mid-sentence
+		api.add_function("reveal_all", |_, t: &mut TextSystem, members, id: Entity| {
+			if let Some(i) = members.slot(id) {
+				t.boxes[i].revealed = t.boxes[i].length() as f64;
+			}
+			Ok(())
+		})?;
+		api.add_function("is_done", |_, t: &mut TextSystem, members, id: Entity| {
+			Ok(members.slot(id).map(|i| t.boxes[i].done()))
+		})?;
+		api.add_function("set_position", |_, t: &mut TextSystem, members, (id, x, y): (Entity, f64, f64)| {
+			if let Some(i) = members.slot(id) {
+				t.boxes[i].x = x;
+				t.boxes[i].y = y;
+			}
+			Ok(())
+		})?;
+		Ok(())
+	}
+	fn tick(&mut self, _: &World, dt: f64) {
+		for b in &mut self.boxes {
+			let length = b.length() as f64;
+			b.revealed = if b.text_speed > 0.0 {
+				(b.revealed + b.text_speed * dt).min(length)
+			} else {
+				length
+			};
+		}
+	}
+	fn write_field(&mut self, slot: usize, field: &str, value: Field) {
+		if let ("position", Field::Vector(x, y)) = (field, value) {
+			self.boxes[slot].x = x;
+			self.boxes[slot].y = y;
+		}
+	}
+	fn spawn<'lua>(&mut self, _: rlua::Context<'lua>, _: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError> {
+		self.boxes.push(rlua_serde::from_value(object).map_err(WorldError::parse)?);
+		Ok(())
+	}
+	fn despawn(&mut self, slot: usize) {
+		self.boxes.swap_remove(slot);
+	}
+	fn get<'lua>(&self, ctx: rlua::Context<'lua>, i: usize) -> Result<rlua::Value<'lua>, WorldError> {
+		rlua_serde::to_value(ctx, &self.boxes[i]).map_err(WorldError::parse)
+	}
+	fn set<'lua>(&mut self, _: rlua::Context<'lua>, i: usize, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+		self.boxes[i] = rlua_serde::from_value(value).map_err(WorldError::parse)?;
+		Ok(())
+	}
+	fn save(&self) -> Result<serde_json::Value, WorldError> {
+		serde_json::to_value(self).map_err(WorldError::parse)
+	}
+	fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError> {
+		*self = serde_json::from_value(state).map_err(WorldError::parse)?;
+		Ok(())
+	}
+	fn slots(&self) -> Result<usize, WorldError> {
+		Ok(self.boxes.len())
+	}
+}
+
+use crate::sdl_renderer::{SdlRenderer, Render};
+impl Render for TextSystem {
+	fn render(&mut self, r: &mut SdlRenderer) {
+		for b in &self.boxes {
+			let style = b.style();
+			let lines = wrap(&b.text, b.width, |s| r.text_width(style, s));
+			let line_height = r.line_height(style);
+			let widths: Vec<u32> = lines.iter().map(|l| r.text_width(style, l.trim_end())).collect();
+			let width = if b.width > 0 { b.width } else { widths.iter().cloned().max().unwrap_or(0) };
+			let height = if b.height > 0 { b.height } else { lines.len() as u32 * line_height };
+			let view = if b.screen_space { View::screen(r.screen_width, r.screen_height) } else { self.view };
+			let (x, y) = view.to_screen(b.x, b.y);
+			let (x, y) = (x as i32, y as i32);
+			let area = Rect::new(x, y, width.max(1), height.max(1));
+
+			if let Some(background) = b.background_color {
+				r.canvas.set_draw_color(Color::from(background));
+				let _ = r.canvas.fill_rect(area);
+			}
+			if b.border_width > 0 {
+				r.canvas.set_draw_color(Color::from(b.border_color));
+				let w = b.border_width;
+				let _ = r.canvas.fill_rects(&[
+					Rect::new(x - w as i32, y - w as i32, width + w * 2, w),
+					Rect::new(x - w as i32, y + height as i32, width + w * 2, w),
+					Rect::new(x - w as i32, y, w, height),
+					Rect::new(x + width as i32, y, w, height),
+				]);
+			}
+
+			//lines that don't fit in the height get cut off
+			let visible = if b.height > 0 { (b.height / line_height.max(1)) as usize } else { lines.len() };
+			let mut budget = b.revealed as usize;
+			for (n, (line, line_width)) in lines.iter().zip(widths).take(visible).enumerate() {
+				let shown: String = line.chars().take(budget).collect();
+				budget -= shown.chars().count();
+				let offset = match b.align {
+					Align::Left => 0,
+					Align::Center => (width as i32 - line_width as i32) / 2,
+					Align::Right => width as i32 - line_width as i32,
+				};
+				r.draw_text(style, &shown, Placement{x: x + offset, y: y + (n as u32 * line_height) as i32, color: Color::from(b.color)});
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	//one unit per character
+	fn count(s: &str) -> u32 {
+		s.chars().count() as u32
+	}
+
+	#[test]
+	fn breaks_at_spaces() {
+		assert_eq!(wrap("the quick brown fox", 10, count), vec!["the quick ", "brown fox"]);
+		//a line that only goes over because of the space it ends with still fits
+		assert_eq!(wrap("abcd efgh", 4, count), vec!["abcd ", "efgh"]);
+		assert_eq!(wrap("one\ntwo three", 5, count), vec!["one", "two ", "three"]);
+	}
+
+	#[test]
+	fn chops_words_too_long_for_a_line() {
+		assert_eq!(wrap("abcdefghij", 4, count), vec!["abcd", "efgh", "ij"]);
+		assert_eq!(wrap("hi abcdefg", 4, count), vec!["hi ", "abcd", "efg"]);
+		//even when not one character fits, each line still gets one
+		assert_eq!(wrap("abc", 1, |s| count(s) * 2), vec!["a", "b", "c"]);
+	}
+
+	#[test]
+	fn zero_width_only_breaks_at_newlines() {
+		assert_eq!(wrap("a long line that goes on\nand another", 0, count), vec!["a long line that goes on", "and another"]);
+		assert_eq!(wrap("", 0, count), vec![""]);
+	}
+
+	#[test]
+	fn every_character_but_newlines_ends_up_on_a_line() {
+		for &text in &["the quick brown fox jumps over the lazy dog", "  spaces   everywhere  ", "a\n\nb c\n", "ünïcödé wörds thät gö ön", "abcdefghijklmnopqrstuvwxyz"] {
+			let text_box: TextBox = serde_json::from_value(serde_json::json!({"text": text})).unwrap();
+			for &width in &[0, 1, 3, 7, 100] {
+				let lines = wrap(text, width, count);
+				assert_eq!(lines.iter().map(|l| l.chars().count()).sum::<usize>(), text_box.length(), "{:?} at {}", text, width);
+				//at least a line per paragraph, empty ones included
+				assert!(lines.len() >= text.split('\n').count());
+			}
+		}
+	}
+}
@@ -0,0 +1,330 @@
+use serde::{Serialize, Deserialize};
+use crate::world::{World, NativeSystem, LuaApi};
+use crate::entity::Entity;
+use crate::error::WorldError;
+use crate::physics::Vector2;
+
+fn map_layer() -> String {
+	"map".to_string()
+}
+
+//what Lua sees, e.g. Tilemap = {tileset = "tiles", tile_width = 32, tile_height = 32, tiles = lvl0, solid = {1, 2}}
+//tiles are rows of tile numbers: 0 is empty, n is the nth tile of the tileset sprite, left to right and then down
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TilemapObject {
+	pub tileset: String,
+	pub tile_width: u32,
+	pub tile_height: u32,
+	//top left corner of the map
+	#[serde(default)]
+	pub x: f64,
+	#[serde(default)]
+	pub y: f64,
+	#[serde(default)]
+	pub tiles: Vec<Vec<u32>>,
+	//tile numbers that things with a Collision shape and solid Physics get pushed out of, none by default
+	#[serde(default)]
+	pub solid: Vec<u32>,
+	//drawn along with sprites, see Render.set_layer
+	#[serde(default = "map_layer")]
+	pub layer: String,
+	#[serde(default)]
+	pub z_index: i32,
+}
+
+//the same thing, with the rows flattened out
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tilemap {
+	pub tileset: String,
+	pub tile_width: u32,
+	pub tile_height: u32,
+	pub x: f64,
+	pub y: f64,
+	pub columns: usize,
+	pub rows: usize,
+	//row by row
+	pub tiles: Vec<u32>,
+	pub solid: Vec<u32>,
+	pub layer: String,
+	pub z_index: i32,
+}
+
+impl Tilemap {
+	fn from_object(o: TilemapObject) -> Result<Tilemap, WorldError> {
+		if o.tile_width == 0 || o.tile_height == 0 {
+			return Err(WorldError::parse("tile_width and tile_height can't be 0"));
+		}
+		//short rows get filled out with empty tiles
+		let columns = o.tiles.iter().map(Vec::len).max().unwrap_or(0);
+		let mut tiles = Vec::with_capacity(columns * o.tiles.len());
+		for row in &o.tiles {
+			tiles.extend(row);
+			tiles.extend(std::iter::repeat_n(0, columns - row.len()));
+		}
+		Ok(Tilemap{tileset: o.tileset, tile_width: o.tile_width, tile_height: o.tile_height, x: o.x, y: o.y,
+			columns, rows: o.tiles.len(), tiles, solid: o.solid, layer: o.layer, z_index: o.z_index})
+	}
+	fn to_object(&self) -> TilemapObject {
+		let tiles = if self.columns == 0 { vec![Vec::new(); self.rows] } else { self.tiles.chunks(self.columns).map(<[u32]>::to_vec).collect() };
+		TilemapObject{tileset: self.tileset.clone(), tile_width: self.tile_width, tile_height: self.tile_height, x: self.x, y: self.y,
+			tiles, solid: self.solid.clone(), layer: self.layer.clone(), z_index: self.z_index}
+	}
+	//0-based, None when it's off the map
+	pub fn get(&self, column: usize, row: usize) -> Option<u32> {
+		if column < self.columns && row < self.rows {
+			Some(self.tiles[row * self.columns + column])
+		} else {
+			None
+		}
+	}
+	//which tile a world position is on, 0-based
+	pub fn cell(&self, x: f64, y: f64) -> Option<(usize, usize)> {
+		let column = ((x - self.x) / self.tile_width as f64).floor();
+		let row = ((y - self.y) / self.tile_height as f64).floor();
+		if column < 0.0 || row < 0.0 || column >= self.columns as f64 || row >= self.rows as f64 {
+			None
+		} else {
+			Some((column as usize, row as usize))
+		}
+	}
+	//the columns and rows that overlap a world area, clamped to the map
+	pub fn cells_in(&self, min: Vector2, max: Vector2) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+		let range = |min: f64, max: f64, origin: f64, size: u32, count: usize| {
+			let first = ((min - origin) / size as f64).floor().max(0.0);
+			let last = ((max - origin) / size as f64).floor().min(count as f64 - 1.0);
+			if last < first {
+				0..0
+			} else {
+				first as usize..last as usize + 1
+			}
+		};
+		(range(min.x, max.x, self.x, self.tile_width, self.columns), range(min.y, max.y, self.y, self.tile_height, self.rows))
+	}
+	//world position of the middle of a tile
+	pub fn center(&self, column: usize, row: usize) -> Vector2 {
+		Vector2{
+			x: self.x + (column as f64 + 0.5) * self.tile_width as f64,
+			y: self.y + (row as f64 + 0.5) * self.tile_height as f64,
+		}
+	}
+}
+
+//grids of tiles drawn out of one tileset sprite, instead of an entity per tile
+//Render draws them (only the tiles the camera can see) in with the sprites, Collision pushes things out of solid tiles
+//indexed by slot, see World::read_members("Tilemap")
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TilemapSystem {
+	pub maps: Vec<Tilemap>,
+}
+
+impl TilemapSystem {
+	//solid tiles overlapping a world area, as the slot of the map they're from, their middle, and half their size
+	pub fn solid_tiles(&self, min: Vector2, max: Vector2) -> Vec<(usize, Vector2, Vector2)> {
+		let mut found = Vec::new();
+		for (slot, map) in self.maps.iter().enumerate() {
+			if map.solid.is_empty() {
+				continue;
+			}
+			let (columns, rows) = map.cells_in(min, max);
+			let half = Vector2{x: map.tile_width as f64 / 2.0, y: map.tile_height as f64 / 2.0};
+			for row in rows {
+				for column in columns.clone() {
+					if map.solid.contains(&map.tiles[row * map.columns + column]) {
+						found.push((slot, map.center(column, row), half));
+					}
+				}
+			}
+		}
+		found
+	}
+}
+
+impl NativeSystem for TilemapSystem {
+	fn new() -> TilemapSystem {
+		TilemapSystem{maps: Vec::new()}
+	}
+	//columns and rows are 1-based on the Lua side, like the tables they came from
+	fn lua_api(&self, api: &LuaApi) -> rlua::Result<()> {
+		//tile, column, row at a world position, or nil off the map
+		api.add_function("tile_at", |_, t: &mut TilemapSystem, members, (id, x, y): (Entity, f64, f64)| {
+			Ok(match members.slot(id).and_then(|i| t.maps[i].cell(x, y).map(|cell| (i, cell))) {
+				Some((i, (column, row))) => (t.maps[i].get(column, row), Some(column + 1), Some(row + 1)),
+				None => (None, None, None),
+			})
+		})?;
+		api.add_function("get_tile", |_, t: &mut TilemapSystem, members, (id, column, row): (Entity, usize, usize)| {
+			Ok(members.slot(id).and_then(|i| t.maps[i].get(column.wrapping_sub(1), row.wrapping_sub(1))))
+		})?;
+		//false if it's off the map, maps don't grow
+		api.add_function("set_tile", |_, t: &mut TilemapSystem, members, (id, column, row, tile): (Entity, usize, usize, u32)| {
+			if let Some(map) = members.slot(id).map(|i| &mut t.maps[i]) {
+				if column >= 1 && row >= 1 && column <= map.columns && row <= map.rows {
+					map.tiles[(row - 1) * map.columns + column - 1] = tile;
+					return Ok(true);
+				}
+			}
+			Ok(false)
+		})?;
+		//world position of the middle of a tile
+		api.add_function("tile_center", |_, t: &mut TilemapSystem, members, (id, column, row): (Entity, usize, usize)| {
+			let (column, row) = (column.wrapping_sub(1), row.wrapping_sub(1));
+			let map = members.slot(id).map(|i| &t.maps[i]).filter(|map| map.get(column, row).is_some());
+			Ok(map.map(|map| map.center(column, row)).map_or((None, None), |p| (Some(p.x), Some(p.y))))
+		})?;
+		api.add_function("set_position", |_, t: &mut TilemapSystem, members, (id, x, y): (Entity, f64, f64)| {
+			if let Some(i) = members.slot(id) {
+				t.maps[i].x = x;
+				t.maps[i].y = y;
+			}
+			Ok(())
+		})?;
+		Ok(())
+	}
+	fn tick(&mut self, _: &World, _: f64) {}
+	fn spawn<'lua>(&mut self, _: rlua::Context<'lua>, _: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let object: TilemapObject = rlua_serde::from_value(object).map_err(WorldError::parse)?;
+		self.maps.push(Tilemap::from_object(object)?);
+		Ok(())
+	}
+	fn despawn(&mut self, slot: usize) {
+		self.maps.swap_remove(slot);
+	}
+	fn get<'lua>(&self, ctx: rlua::Context<'lua>, i: usize) -> Result<rlua::Value<'lua>, WorldError> {
+		rlua_serde::to_value(ctx, self.maps[i].to_object()).map_err(WorldError::parse)
+	}
+	fn set<'lua>(&mut self, _: rlua::Context<'lua>, i: usize, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let object: TilemapObject = rlua_serde::from_value(value).map_err(WorldError::parse)?;
+		self.maps[i] = Tilemap::from_object(object)?;
+		Ok(())
+	}
+	fn save(&self) -> Result<serde_json::Value, WorldError> {
+		serde_json::to_value(self).map_err(WorldError::parse)
+	}
+	fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError> {
+		*self = serde_json::from_value(state).map_err(WorldError::parse)?;
+		Ok(())
+	}
+	//a map's tiles have to fill it exactly, everything indexes them by row * columns + column
+	fn slots(&self) -> Result<usize, WorldError> {
+		for (slot, map) in self.maps.iter().enumerate() {
+			if map.columns.checked_mul(map.rows) != Some(map.tiles.len()) {
+				return Err(WorldError::parse(format!("map {} is {}x{} but has {} tiles", slot, map.columns, map.rows, map.tiles.len())));
+			}
+		}
+		Ok(self.maps.len())
+	}
+}
+
+use crate::camera::View;
+use crate::sdl_renderer::SdlRenderer;
+use sdl2::rect::Rect;
+impl TilemapSystem {
+	//only the tiles inside the view's viewport, all out of the one tileset
+	pub fn draw(&self, slot: usize, r: &mut SdlRenderer, view: &View) {
+		let map = &self.maps[slot];
+		let (texture, tileset) = match r.atlas.get(&map.tileset) {
+			Some(found) => found,
+			None => return,
+		};
+		let per_row = (tileset.width() / map.tile_width).max(1);
+		//the viewport's corners in the world, which is a rotated rectangle if the camera is turned
+		let area = view.viewport;
+		let corners = [(area.x, area.y), (area.x + area.width, area.y), (area.x, area.y + area.height), (area.x + area.width, area.y + area.height)];
+		let corners: Vec<(f64, f64)> = corners.iter().map(|&(x, y)| view.to_world(x, y)).collect();
+		let min = Vector2{x: corners.iter().map(|c| c.0).fold(f64::INFINITY, f64::min), y: corners.iter().map(|c| c.1).fold(f64::INFINITY, f64::min)};
+		let max = Vector2{x: corners.iter().map(|c| c.0).fold(f64::NEG_INFINITY, f64::max), y: corners.iter().map(|c| c.1).fold(f64::NEG_INFINITY, f64::max)};
+		let (columns, rows) = map.cells_in(min, max);
+		//rounded up so there aren't gaps between tiles when zoomed
+		let (width, height) = ((map.tile_width as f64 * view.zoom).ceil(), (map.tile_height as f64 * view.zoom).ceil());
+		for row in rows {
+			for column in columns.clone() {
+				let tile = map.tiles[row * map.columns + column];
+				if tile == 0 {
+					continue;
+				}
+				let n = tile - 1;
+				let src = Rect::new(
+					tileset.x() + ((n % per_row) * map.tile_width) as i32,
+					tileset.y() + ((n / per_row) * map.tile_height) as i32,
+					map.tile_width, map.tile_height);
+				//past the end of the tileset
+				if src.bottom() > tileset.bottom() {
+					continue;
+				}
+				let center = map.center(column, row);
+				let (x, y) = view.to_screen(center.x, center.y);
+				let dst = Rect::new((x - width / 2.0).round() as i32, (y - height / 2.0).round() as i32, width as u32, height as u32);
+				let _ = r.canvas.copy_ex(texture, src, dst, -view.rotation, None, false, false);
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn v(x: f64, y: f64) -> Vector2 {
+		Vector2{x, y}
+	}
+
+	//4 columns of 10 by 20 pixel tiles, 3 rows, starting at 100, 50
+	fn map() -> Tilemap {
+		let object: TilemapObject = serde_json::from_value(serde_json::json!({
+			"tileset": "tiles", "tile_width": 10, "tile_height": 20, "x": 100, "y": 50,
+			"tiles": [[1, 2], [0, 0, 0, 3], [1]],
+		})).unwrap();
+		Tilemap::from_object(object).unwrap()
+	}
+
+	#[test]
+	fn short_rows_get_filled_out() {
+		let map = map();
+		assert_eq!((map.columns, map.rows), (4, 3));
+		assert_eq!(map.tiles, vec![1, 2, 0, 0, 0, 0, 0, 3, 1, 0, 0, 0]);
+		assert_eq!(map.to_object().tiles, vec![vec![1, 2, 0, 0], vec![0, 0, 0, 3], vec![1, 0, 0, 0]]);
+	}
+
+	#[test]
+	fn cells_inside_the_map() {
+		let map = map();
+		assert_eq!(map.cells_in(v(115.0, 55.0), v(125.0, 89.0)), (1..3, 0..2));
+		//a point is the one cell it's in
+		assert_eq!(map.cells_in(v(135.0, 95.0), v(135.0, 95.0)), (3..4, 2..3));
+	}
+
+	#[test]
+	fn cells_get_clamped_to_the_map() {
+		let map = map();
+		assert_eq!(map.cells_in(v(-1000.0, -1000.0), v(1000.0, 1000.0)), (0..4, 0..3));
+		assert_eq!(map.cells_in(v(90.0, 100.0), v(105.0, 1000.0)), (0..1, 2..3));
+		//nowhere near it, on every side
+		for &(min, max) in &[(v(0.0, 0.0), v(99.0, 200.0)), (v(140.0, 0.0), v(500.0, 200.0)), (v(0.0, 0.0), v(200.0, 49.0)), (v(0.0, 110.0), v(200.0, 300.0))] {
+			let (columns, rows) = map.cells_in(min, max);
+			assert!(columns.is_empty() || rows.is_empty(), "{:?} to {:?} gave {:?}, {:?}", min, max, columns, rows);
+		}
+	}
+
+	#[test]
+	fn empty_maps_have_no_cells() {
+		let object: TilemapObject = serde_json::from_value(serde_json::json!({"tileset": "tiles", "tile_width": 10, "tile_height": 10})).unwrap();
+		let map = Tilemap::from_object(object).unwrap();
+		let (columns, rows) = map.cells_in(v(-100.0, -100.0), v(100.0, 100.0));
+		assert!(columns.is_empty() && rows.is_empty());
+	}
+
+	#[test]
+	fn solid_tiles_say_which_slot_they_came_from() {
+		let mut solid = map();
+		solid.solid = vec![3];
+		let mut far = solid.clone();
+		far.x = 1000.0;
+		let tiles = TilemapSystem{maps: vec![map(), far, solid]};
+		let found = tiles.solid_tiles(v(-1000.0, -1000.0), v(500.0, 500.0));
+		//the first map has nothing solid and the second is out of range
+		assert_eq!(found.len(), 1);
+		let (slot, center, half) = found[0];
+		assert_eq!(slot, 2);
+		assert_eq!((center.x, center.y, half.x, half.y), (135.0, 80.0, 5.0, 10.0));
+	}
+}
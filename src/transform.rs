@@ -0,0 +1,288 @@
+use serde::{Serialize, Deserialize};
+use crate::world::{World, NativeSystem, LuaApi, Field, slot_count};
+use crate::entity::Entity;
+use crate::error::WorldError;
+use crate::physics::{BodyKind, PhysicsSystem, Vector2};
+
+fn one() -> Vector2 {
+	Vector2{x: 1.0, y: 1.0}
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Transform {
+	#[serde(default)]
+	pub position: Vector2,
+	#[serde(default)]
+	pub rotation: f64, //degrees, same as Physics angles
+	#[serde(default = "one")]
+	pub scale: Vector2,
+}
+
+impl Default for Transform {
+	fn default() -> Transform {
+		Transform{position: Vector2::default(), rotation: 0.0, scale: one()}
+	}
+}
+
+impl Transform {
+	//this transform, taken as relative to parent
+	pub fn in_parent(&self, parent: &Transform) -> Transform {
+		let (sin, cos) = parent.rotation.to_radians().sin_cos();
+		let (x, y) = (self.position.x * parent.scale.x, self.position.y * parent.scale.y);
+		Transform{
+			position: Vector2{x: parent.position.x + x * cos - y * sin, y: parent.position.y + x * sin + y * cos},
+			rotation: parent.rotation + self.rotation,
+			scale: Vector2{x: parent.scale.x * self.scale.x, y: parent.scale.y * self.scale.y},
+		}
+	}
+}
+
+//what Lua sees, e.g. Transform = {position = {x = 16, y = 0}, parent = player_id}
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct TransformObject {
+	#[serde(default)]
+	pub position: Vector2,
+	#[serde(default)]
+	pub rotation: f64,
+	#[serde(default = "one")]
+	pub scale: Vector2,
+	#[serde(default)]
+	pub parent: Option<Entity>,
+}
+
+//parents always come before their children; a loop of parents gets cut by making one of them a root
+fn hierarchy_order(parents: &mut [Option<usize>]) -> Vec<usize> {
+	let mut order = Vec::with_capacity(parents.len());
+	//0 = not seen yet, 1 = on the chain being walked, 2 = already in order
+	let mut state = vec![0u8; parents.len()];
+	for start in 0..parents.len() {
+		let mut chain = Vec::new();
+		let mut current = Some(start);
+		while let Some(i) = current {
+			if state[i] == 2 {
+				break;
+			}
+			if state[i] == 1 {
+				parents[*chain.last().unwrap()] = None;
+				break;
+			}
+			state[i] = 1;
+			chain.push(i);
+			current = parents[i];
+		}
+		for &i in chain.iter().rev() {
+			state[i] = 2;
+			order.push(i);
+		}
+	}
+	order
+}
+
+//local transforms relative to an optional parent entity, turned into world transforms every tick
+//roots that also have Physics follow it; children with Physics get moved to wherever their parent puts them
+//a child whose parent is gone (or never had a Transform) acts as a root
+//indexed by slot, see World::read_members("Transform")
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TransformSystem {
+	locals: Vec<Transform>,
+	parents: Vec<Option<Entity>>,
+	//as of the last tick
+	pub world_transforms: Vec<Transform>,
+}
+
+impl TransformSystem {
+	fn update(&mut self, parent_slots: &mut [Option<usize>], physics_slots: &[Option<usize>], mut ph: Option<&mut PhysicsSystem>) {
+		let order = hierarchy_order(parent_slots);
+		if let Some(ph) = ph.as_mut() {
+			for (i, physics_slot) in physics_slots.iter().enumerate() {
+				if let (None, Some(p)) = (parent_slots[i], physics_slot) {
+					self.locals[i].position = ph.positions[*p];
+					self.locals[i].rotation = ph.angles[*p];
+				}
+			}
+		}
+		for i in order {
+			self.world_transforms[i] = match parent_slots[i] {
+				Some(parent) => self.locals[i].in_parent(&self.world_transforms[parent]),
+				None => self.locals[i],
+			};
+		}
+		if let Some(ph) = ph {
+			for (i, physics_slot) in physics_slots.iter().enumerate() {
+				if let (Some(_), Some(p)) = (parent_slots[i], physics_slot) {
+					let Transform{position, rotation, ..} = self.world_transforms[i];
+					if ph.kinds[*p] == BodyKind::Static && (ph.positions[*p].x != position.x || ph.positions[*p].y != position.y) {
+						ph.statics_changed += 1;
+					}
+					ph.positions[*p] = position;
+					ph.angles[*p] = rotation;
+				}
+			}
+		}
+	}
+}
+
+impl NativeSystem for TransformSystem {
+	fn new() -> TransformSystem {
+		TransformSystem{locals: Vec::new(), parents: Vec::new(), world_transforms: Vec::new()}
+	}
+	fn lua_api(&self, api: &LuaApi) -> rlua::Result<()> {
+		//local position, relative to the parent
+		api.add_function("set_position", |_, t: &mut TransformSystem, members, (id, x, y): (Entity, f64, f64)| {
+			if let Some(i) = members.slot(id) {
+				t.locals[i].position = Vector2{x, y};
+			}
+			Ok(())
+		})?;
+		api.add_function("get_position", |_, t: &mut TransformSystem, members, id: Entity| {
+			Ok(members.slot(id).map(|i| t.locals[i].position).map_or((None, None), |p| (Some(p.x), Some(p.y))))
+		})?;
+		api.add_function("set_rotation", |_, t: &mut TransformSystem, members, (id, rotation): (Entity, f64)| {
+			if let Some(i) = members.slot(id) {
+				t.locals[i].rotation = rotation;
+			}
+			Ok(())
+		})?;
+		api.add_function("set_scale", |_, t: &mut TransformSystem, members, (id, x, y): (Entity, f64, Option<f64>)| {
+			if let Some(i) = members.slot(id) {
+				t.locals[i].scale = Vector2{x, y: y.unwrap_or(x)};
+			}
+			Ok(())
+		})?;
+		api.add_function("set_parent", |_, t: &mut TransformSystem, members, (id, parent): (Entity, Option<Entity>)| {
+			if let Some(i) = members.slot(id) {
+				t.parents[i] = parent;
+			}
+			Ok(())
+		})?;
+		api.add_function("get_parent", |_, t: &mut TransformSystem, members, id: Entity| {
+			Ok(members.slot(id).and_then(|i| t.parents[i]))
+		})?;
+		//where the hierarchy put it on the last tick
+		api.add_function("get_world_position", |_, t: &mut TransformSystem, members, id: Entity| {
+			Ok(members.slot(id).map(|i| t.world_transforms[i].position).map_or((None, None), |p| (Some(p.x), Some(p.y))))
+		})?;
+		api.add_function("get_world_rotation", |_, t: &mut TransformSystem, members, id: Entity| {
+			Ok(members.slot(id).map(|i| t.world_transforms[i].rotation))
+		})?;
+		Ok(())
+	}
+	//reads Physics positions for roots and writes them for children, so it goes after Physics and Collision
+	fn tick(&mut self, world: &World, _: f64) {
+		let entities = match world.members("Transform") {
+			Ok(entities) => entities,
+			Err(_) => return,
+		};
+		let parents = &self.parents;
+		let mut parent_slots: Vec<Option<usize>> = match world.read_members("Transform", |m| parents.iter().map(|p| p.and_then(|p| m.slot(p))).collect()) {
+			Ok(slots) => slots,
+			Err(_) => return,
+		};
+		let physics_slots: Vec<Option<usize>> = world.read_members("Physics", |m| entities.iter().map(|&e| m.slot(e)).collect())
+			.unwrap_or_else(|_| vec![None; entities.len()]);
+		let mut updated = false;
+		let _ = world.write_native_system("Physics", |ph: &mut PhysicsSystem| {
+			self.update(&mut parent_slots, &physics_slots, Some(ph));
+			updated = true;
+		});
+		if !updated {
+			self.update(&mut parent_slots, &physics_slots, None);
+		}
+	}
+	//world values, as of the last tick
+	fn read_field(&self, slot: usize, field: &str) -> Option<Field> {
+		let Transform{position, rotation, scale} = self.world_transforms[slot];
+		match field {
+			"position" => Some(Field::Vector(position.x, position.y)),
+			"rotation" => Some(Field::Number(rotation)),
+			"scale" => Some(Field::Vector(scale.x, scale.y)),
+			_ => None,
+		}
+	}
+	fn spawn<'lua>(&mut self, _: rlua::Context<'lua>, _: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let TransformObject{position, rotation, scale, parent} = rlua_serde::from_value(object).map_err(WorldError::parse)?;
+		let local = Transform{position, rotation, scale};
+		self.locals.push(local);
+		self.parents.push(parent);
+		//good enough until the next tick works out the real one
+		self.world_transforms.push(local);
+		Ok(())
+	}
+	fn despawn(&mut self, slot: usize) {
+		self.locals.swap_remove(slot);
+		self.parents.swap_remove(slot);
+		self.world_transforms.swap_remove(slot);
+	}
+	fn get<'lua>(&self, ctx: rlua::Context<'lua>, i: usize) -> Result<rlua::Value<'lua>, WorldError> {
+		let Transform{position, rotation, scale} = self.locals[i];
+		rlua_serde::to_value(ctx, TransformObject{position, rotation, scale, parent: self.parents[i]}).map_err(WorldError::parse)
+	}
+	fn set<'lua>(&mut self, _: rlua::Context<'lua>, i: usize, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let TransformObject{position, rotation, scale, parent} = rlua_serde::from_value(value).map_err(WorldError::parse)?;
+		self.locals[i] = Transform{position, rotation, scale};
+		self.parents[i] = parent;
+		Ok(())
+	}
+	fn save(&self) -> Result<serde_json::Value, WorldError> {
+		serde_json::to_value(self).map_err(WorldError::parse)
+	}
+	fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError> {
+		*self = serde_json::from_value(state).map_err(WorldError::parse)?;
+		Ok(())
+	}
+	fn slots(&self) -> Result<usize, WorldError> {
+		slot_count(&[("locals", self.locals.len()), ("parents", self.parents.len()), ("world_transforms", self.world_transforms.len())])
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_parents_first(order: &[usize], parents: &[Option<usize>]) {
+		let mut sorted = order.to_vec();
+		sorted.sort_unstable();
+		assert_eq!(sorted, (0..parents.len()).collect::<Vec<_>>());
+		let position = |i: usize| order.iter().position(|&o| o == i).unwrap();
+		for (i, parent) in parents.iter().enumerate() {
+			if let Some(parent) = *parent {
+				assert!(position(parent) < position(i), "{} came before its parent {} in {:?}", i, parent, order);
+			}
+		}
+	}
+
+	#[test]
+	fn parents_come_before_their_children() {
+		let mut parents = [Some(2), None, Some(1), Some(0), None];
+		let order = hierarchy_order(&mut parents);
+		assert_eq!(order, vec![1, 2, 0, 3, 4]);
+		assert_parents_first(&order, &parents);
+		//nothing to cut
+		assert_eq!(parents, [Some(2), None, Some(1), Some(0), None]);
+	}
+
+	#[test]
+	fn loops_get_cut_where_they_close() {
+		let mut parents = [Some(1), Some(2), Some(0), Some(0)];
+		let order = hierarchy_order(&mut parents);
+		assert_eq!(parents, [Some(1), Some(2), None, Some(0)]);
+		assert_parents_first(&order, &parents);
+	}
+
+	#[test]
+	fn only_the_loop_gets_cut_not_whatever_hangs_off_it() {
+		//0 points into the 1 <-> 2 loop without being part of it
+		let mut parents = [Some(1), Some(2), Some(1)];
+		let order = hierarchy_order(&mut parents);
+		assert_eq!(parents, [Some(1), Some(2), None]);
+		assert_parents_first(&order, &parents);
+	}
+
+	#[test]
+	fn own_parent() {
+		let mut parents = [None, Some(1)];
+		let order = hierarchy_order(&mut parents);
+		assert_eq!(parents, [None, None]);
+		assert_eq!(order, vec![0, 1]);
+	}
+}
@@ -1,42 +1,101 @@
 use std::collections::HashMap;
 use std::fmt::Debug;
+use crate::entity::{Entity, EntityAllocator, Members};
+use crate::error::WorldError;
+use crate::schedule::{Link, Schedule, SystemOptions};
+use rayon::prelude::*;
 
 pub trait NativeSystem: Debug + Send + Sync + 'static {
 	fn new() -> Self where Self: Sized;
-	fn tick(&mut self, world: &World);
-	fn globals(&self, _ctx: rlua::Context) {}
-	fn spawn(&mut self, entity: usize, object: rlua::Value);
-	fn get<'lua>(&self, ctx: rlua::Context<'lua>, entity: usize) -> rlua::Value<'lua>;
-	fn set<'lua>(&mut self, entity: usize, value: rlua::Value<'lua>);
-	fn save(&self) -> serde_json::Value;
+	//dt is the fixed timestep in seconds
+	fn tick(&mut self, world: &World, dt: f64);
+	fn globals(&self, _ctx: rlua::Context) -> rlua::Result<()> {
+		Ok(())
+	}
+	//register functions under the system's own Lua table, e.g. Physics.set_velocity(id, x, y)
+	fn lua_api(&self, _api: &LuaApi) -> rlua::Result<()> {
+		Ok(())
+	}
+	fn spawn<'lua>(&mut self, ctx: rlua::Context<'lua>, entity: Entity, object: rlua::Value<'lua>) -> Result<(), WorldError>;
+	//slots are handed out by the World: spawn pushes onto the end, despawn swap_removes
+	fn despawn(&mut self, slot: usize);
+	fn get<'lua>(&self, ctx: rlua::Context<'lua>, slot: usize) -> Result<rlua::Value<'lua>, WorldError>;
+	fn set<'lua>(&mut self, ctx: rlua::Context<'lua>, slot: usize, value: rlua::Value<'lua>) -> Result<(), WorldError>;
+	fn save(&self) -> Result<serde_json::Value, WorldError>;
+	fn load(&mut self, state: serde_json::Value) -> Result<(), WorldError>;
+	//how many slots there are, or a parse error if the system's own lists don't agree on that
+	//a loaded state has to come out to as many as the snapshot has members, see slot_count
+	fn slots(&self) -> Result<usize, WorldError>;
+	//Lua functions to call once tick is done (tick might not even be on the Lua thread)
+	//the World calls them after letting go of the system's lock, so they can use the world freely
+	//members are passed along so slots can be turned back into entity ids
+	fn callbacks<'lua>(&mut self, _ctx: rlua::Context<'lua>, _members: &Members) -> Vec<Callback<'lua>> {
+		Vec::new()
+	}
+	//named values other systems can declare links to, see SystemOptions::link
+	fn read_field(&self, _slot: usize, _field: &str) -> Option<Field> {
+		None
+	}
+	fn write_field(&mut self, _slot: usize, _field: &str, _value: Field) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+	Number(f64),
+	Vector(f64, f64),
+}
+
+pub type Callback<'lua> = (rlua::Function<'lua>, rlua::MultiValue<'lua>);
+
+//for NativeSystem::slots, e.g. slot_count(&[("positions", positions.len()), ("velocities", velocities.len())])
+pub fn slot_count(lists: &[(&str, usize)]) -> Result<usize, WorldError> {
+	let (first, count) = lists.first().cloned().unwrap_or(("", 0));
+	match lists.iter().find(|(_, len)| *len != count) {
+		Some((name, len)) => Err(WorldError::parse(format!("{} has {} slots but {} has {}", first, count, name, len))),
+		None => Ok(count),
+	}
 }
 
 //The folowing set of functions allow for getting a specific NativeSystem from the world, and will not work otherwise
 pub trait AnyNativeSystem: NativeSystem + std::any::Any {
-    fn as_any(&self) -> &std::any::Any;
-    fn as_any_mut(&mut self) -> &mut std::any::Any;
+    fn as_any(&self) -> &dyn std::any::Any;
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any;
+    //what a freshly made system saves as, for emptying one out with load
+    fn empty_state(&self) -> Result<serde_json::Value, WorldError>;
+    //loads into a throwaway system, so a bad state can be caught before anything changes; gives back how many slots it has
+    fn check_state(&self, state: serde_json::Value) -> Result<usize, WorldError>;
 }
 
 
 impl dyn AnyNativeSystem {
     fn to_system<T: NativeSystem>(&self) -> Option<&T> {
-        std::any::Any::downcast_ref::<T>(self.as_any())
+        self.as_any().downcast_ref::<T>()
     }
     fn to_system_mut<T: NativeSystem>(&mut self) -> Option<&mut T> {
-    	std::any::Any::downcast_mut::<T>(self.as_any_mut())
+    	self.as_any_mut().downcast_mut::<T>()
     }
 }
 
 impl<T> AnyNativeSystem for T
     where T: NativeSystem + std::any::Any
 {
-    fn as_any(&self) -> &std::any::Any {
+    fn as_any(&self) -> &dyn std::any::Any {
         self
     }
 
-    fn as_any_mut(&mut self) -> &mut std::any::Any {
+    fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
         self
     }
+
+    fn empty_state(&self) -> Result<serde_json::Value, WorldError> {
+        T::new().save()
+    }
+
+    fn check_state(&self, state: serde_json::Value) -> Result<usize, WorldError> {
+        let mut system = T::new();
+        system.load(state)?;
+        system.slots()
+    }
 }
 
 enum System {
@@ -45,198 +104,663 @@ enum System {
 }
 
 impl System {
-	fn as_native_system(&self) -> &Box<dyn AnyNativeSystem> {
+	fn as_native_system(&self) -> &dyn AnyNativeSystem {
 		if let System::NativeSys(s) = self {
-			s
+			s.as_ref()
 		} else {
 			panic!("not a NativeSystem");
 		}
 	}
-	fn as_native_system_mut(&mut self) -> &mut Box<dyn AnyNativeSystem> {
+	fn as_native_system_mut(&mut self) -> &mut dyn AnyNativeSystem {
 		if let System::NativeSys(s) = self {
-			s
+			s.as_mut()
 		} else {
 			panic!("not a NativeSystem");
 		}
 	}
 }
 
-use std::sync::RwLock;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+
+//the World keeps track of which entities belong to a system, so the system only has to store data
+struct SystemEntry {
+	system: RwLock<System>,
+	members: RwLock<Members>,
+}
+
+impl SystemEntry {
+	fn new(system: System) -> SystemEntry {
+		SystemEntry{system: RwLock::new(system), members: RwLock::new(Members::new())}
+	}
+	//a Lua system's table, None for a native system
+	//the lock is let go of before this returns, so whatever gets called on the table can use the world (and this system) freely
+	fn lua_table<'lua>(&self, ctx: rlua::Context<'lua>, name: &str) -> Result<Option<rlua::Table<'lua>>, WorldError> {
+		match *self.system.read()? {
+			System::LuaSys(ref key) => ctx.registry_value(key).map(Some).map_err(|e| WorldError::lua(name, e)),
+			System::NativeSys(_) => Ok(None),
+		}
+	}
+}
 
 //Component Name, System
 pub struct World {
-	systems: RwLock<HashMap<String, RwLock<System>>>,
-	base_id: AtomicUsize,
+	systems: RwLock<HashMap<String, Arc<SystemEntry>>>,
+	schedule: RwLock<Schedule>,
+	entities: RwLock<EntityAllocator>,
 }
 
 #[allow(unused)]
 impl World {
 	pub fn new() -> World {
 		World {
-			base_id: AtomicUsize::new(0),
+			entities: RwLock::new(EntityAllocator::new()),
 			systems: RwLock::new(HashMap::new()),
+			schedule: RwLock::new(Schedule::new()),
 		}
 	}
-	pub fn tick(&self, ctx: rlua::Context) {
-		for (_, v) in self.systems.read().unwrap().iter() {
-			match *v.write().unwrap() {
-				System::LuaSys(ref mut v) => {
-					let table: rlua::Table = ctx.registry_value(&v).unwrap();
-					if let Ok(function) = table.get::<_, rlua::Function>("tick") {
-						function.call::<rlua::Table, ()>(table).unwrap();
+	//a failing system doesn't stop the rest from ticking, all of the errors get returned together
+	//native systems that declared their access get batched by the schedule and run on the rayon pool,
+	//Lua systems always run here since ctx can't leave this thread
+	pub fn tick(&self, ctx: rlua::Context, dt: f64) -> Result<(), WorldError> {
+		let mut errors = Vec::new();
+		let (batches, links) = {
+			let schedule = self.schedule.read()?;
+			let batches = schedule.batches();
+			let links: HashMap<String, Vec<Link>> = batches.iter().flatten().map(|k| (k.clone(), schedule.links(k).to_vec())).collect();
+			(batches, links)
+		};
+		for batch in &batches {
+			let entries: Vec<(&String, Arc<SystemEntry>)> = {
+				let systems = self.systems.read()?;
+				batch.iter().filter_map(|k| systems.get(k).map(|v| (k, v.clone()))).collect()
+			};
+			//links are done here, before anything in the batch starts
+			for (k, v) in &entries {
+				for link in &links[*k] {
+					if let Err(e) = self.sync_link(v, link) {
+						errors.push(e);
 					}
-				},
-				System::NativeSys(ref mut v) => {
-					v.tick(self);
 				}
 			}
-		}
-	}
-
-	pub fn spawn<'lua>(&self, ctx: rlua::Context<'lua>, components: rlua::Table<'lua>) -> usize {
-		let id = self.base_id.fetch_add(1, Ordering::SeqCst);
-		for (k, v) in self.systems.read().unwrap().iter() {
-			if let Ok(object) = components.get::<&str, rlua::Table>(k) {
-				match *v.write().unwrap() {
-					System::NativeSys(ref mut v) => v.spawn(id, rlua::Value::Table(object)),
-					System::LuaSys(ref mut v) => {
-						object.set("id", id).unwrap();
-						let table: rlua::Table = ctx.registry_value(&v).unwrap();
-						if let Ok(function) = table.get::<_, rlua::Function>("spawn") {
-							function.call::<(rlua::Table, rlua::Table), ()>((table, object)).unwrap();
+			if entries.len() > 1 {
+				let results: Vec<Result<(), WorldError>> = entries.par_iter().map(|(_, v)| {
+					v.system.write()?.as_native_system_mut().tick(self, dt);
+					Ok(())
+				}).collect();
+				errors.extend(results.into_iter().filter_map(Result::err));
+				for (k, v) in &entries {
+					self.run_callbacks(ctx, k, v, &mut errors);
+				}
+				continue;
+			}
+			for (k, v) in entries {
+				let mut system = v.system.write()?;
+				match *system {
+					System::LuaSys(ref v) => {
+						//the lock isn't needed once we have the table, and holding it would deadlock
+						//a tick that calls world:get on its own system
+						let table = ctx.registry_value::<rlua::Table>(v);
+						drop(system);
+						let result = table.and_then(|table| {
+							match table.get::<_, rlua::Function>("tick") {
+								Ok(function) => function.call::<_, ()>((table, dt)),
+								Err(_) => Ok(()),
+							}
+						});
+						if let Err(e) = result {
+							errors.push(WorldError::lua(k, e));
 						}
+					},
+					System::NativeSys(ref mut sys) => {
+						sys.tick(self, dt);
+						drop(system);
+						self.run_callbacks(ctx, k, &v, &mut errors);
 					}
 				}
-			}		
+			}
 		}
-		id
-	}
-
-	pub fn get<'lua>(&self, ctx: rlua::Context<'lua>, name: String, entity: usize) -> rlua::Value<'lua> {
-		if let Some(ref sys_lock) = self.systems.read().unwrap().get(&name) {
-			match *sys_lock.read().unwrap() {
-				System::NativeSys(ref sys) => sys.get(ctx, entity).clone(),
-				System::LuaSys(ref sys) => {
-					let table: rlua::Table = ctx.registry_value(&sys).unwrap();
-					if let Ok(function) = table.get::<_, rlua::Function>("get") {
-						function.call::<(rlua::Table, usize), rlua::Value>((table, entity)).unwrap()
-					} else {
-						rlua::Value::Nil
-					}
+		match errors.len() {
+			0 => Ok(()),
+			1 => Err(errors.remove(0)),
+			_ => Err(WorldError::Multiple(errors)),
+		}
+	}
+
+	//copies a linked field out of the source system for every entity that's in both, see SystemOptions::link
+	//a source that isn't registered is skipped, same as before/after constraints
+	fn sync_link(&self, target: &SystemEntry, link: &Link) -> Result<(), WorldError> {
+		let source = match self.systems.read()?.get(&link.source) {
+			Some(source) => source.clone(),
+			None => return Ok(()),
+		};
+		//a system linking to itself would deadlock on its own lock
+		if std::ptr::eq(&*source, target) {
+			return Ok(());
+		}
+		let source_members = source.members.read()?;
+		let target_members = target.members.read()?;
+		let source_system = source.system.read()?;
+		let mut target_system = target.system.write()?;
+		if let (System::NativeSys(from), System::NativeSys(into)) = (&*source_system, &mut *target_system) {
+			for (slot, entity) in target_members.iter().enumerate() {
+				if let Some(value) = source_members.slot(entity).and_then(|s| from.read_field(s, &link.field)) {
+					into.write_field(slot, &link.into, value);
 				}
 			}
-		} else {
-			rlua::Value::Nil
 		}
+		Ok(())
 	}
-	pub fn set<'lua>(&self, ctx: rlua::Context<'lua>, name: String, entity: usize, value: rlua::Value<'lua>) {
-		if let Some(ref sys_lock) = self.systems.read().unwrap().get(&name) {
-			match *sys_lock.write().unwrap() {
-				System::NativeSys(ref mut sys) => sys.set(entity, value),
-				System::LuaSys(ref sys) => {
-					let table: rlua::Table = ctx.registry_value(&sys).unwrap();
-					if let Ok(function) = table.get::<_, rlua::Function>("set") {
-						function.call::<(rlua::Table, usize, rlua::Value), ()>((table, entity, value)).unwrap();
-					}
+
+	//calls whatever a native system queued up during its tick, see NativeSystem::callbacks
+	fn run_callbacks(&self, ctx: rlua::Context, name: &str, entry: &SystemEntry, errors: &mut Vec<WorldError>) {
+		//same order LuaApi takes these two in
+		let callbacks = match (entry.members.read(), entry.system.write()) {
+			(Ok(members), Ok(mut system)) => match *system {
+				System::NativeSys(ref mut sys) => sys.callbacks(ctx, &members),
+				System::LuaSys(_) => return,
+			},
+			_ => return errors.push(WorldError::LockPoisoned),
+		};
+		for (function, args) in callbacks {
+			if let Err(e) = function.call::<_, ()>(args) {
+				errors.push(WorldError::lua(name, e));
+			}
+		}
+	}
+
+	//the systems lock only has to be held long enough to clone these out,
+	//anything that calls into Lua works off of the clones so the script can use the world too
+	fn entry(&self, name: &str) -> Result<Arc<SystemEntry>, WorldError> {
+		self.systems.read()?.get(name).cloned().ok_or_else(|| WorldError::UnknownSystem(name.to_string()))
+	}
+	fn entries(&self) -> Result<Vec<(String, Arc<SystemEntry>)>, WorldError> {
+		Ok(self.systems.read()?.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+	}
+
+	pub fn is_alive(&self, entity: Entity) -> bool {
+		self.entities.read().map(|e| e.is_alive(entity)).unwrap_or(false)
+	}
+	pub fn entities(&self) -> Vec<Entity> {
+		self.entities.read().map(|e| e.iter().collect()).unwrap_or_default()
+	}
+	//entities belonging to a system, in slot order
+	pub fn members(&self, name: &str) -> Result<Vec<Entity>, WorldError> {
+		self.read_members(name, |m| m.entities().to_vec())
+	}
+	pub fn read_members<R, F>(&self, name: &str, f: F) -> Result<R, WorldError>
+	where F: FnOnce(&Members) -> R {
+		let systems = self.systems.read()?;
+		let entry = systems.get(name).ok_or_else(|| WorldError::UnknownSystem(name.to_string()))?;
+		let members = entry.members.read()?;
+		Ok(f(&members))
+	}
+	pub fn has_all<S: AsRef<str>>(&self, entity: Entity, names: &[S]) -> Result<bool, WorldError> {
+		let systems = self.systems.read()?;
+		for name in names {
+			let entry = systems.get(name.as_ref()).ok_or_else(|| WorldError::UnknownSystem(name.as_ref().to_string()))?;
+			if !entry.members.read()?.contains(entity) {
+				return Ok(false);
+			}
+		}
+		Ok(true)
+	}
+	//entities that belong to every one of the named systems
+	//walks the smallest membership set and checks the rest, nothing gets marshalled
+	pub fn query<S: AsRef<str>>(&self, names: &[S]) -> Result<Vec<Entity>, WorldError> {
+		let systems = self.systems.read()?;
+		let mut sets = Vec::new();
+		for name in names {
+			let entry = systems.get(name.as_ref()).ok_or_else(|| WorldError::UnknownSystem(name.as_ref().to_string()))?;
+			sets.push(entry.members.read()?);
+		}
+		sets.sort_by_key(|m| m.len());
+		Ok(match sets.split_first() {
+			Some((smallest, rest)) => smallest.iter()
+				.filter(|&e| rest.iter().all(|m| m.contains(e)))
+				.collect(),
+			None => Vec::new(),
+		})
+	}
+
+	//if any system refuses the entity, whatever got spawned so far is despawned again
+	pub fn spawn<'lua>(&self, ctx: rlua::Context<'lua>, components: rlua::Table<'lua>) -> Result<Entity, WorldError> {
+		let id = self.entities.write()?.allocate();
+		let result = self.spawn_components(ctx, id, components);
+		if result.is_err() {
+			self.despawn(ctx, id)?;
+		}
+		result.map(|_| id)
+	}
+	fn spawn_components<'lua>(&self, ctx: rlua::Context<'lua>, id: Entity, components: rlua::Table<'lua>) -> Result<(), WorldError> {
+		for (k, v) in self.entries()? {
+			if let Ok(object) = components.get::<&str, rlua::Table>(&k) {
+				//a member before its spawn runs, so a Lua spawn can already world:get itself
+				v.members.write()?.insert(id);
+				let result = match v.lua_table(ctx, &k)? {
+					Some(table) => object.set("id", id).and_then(|_| {
+						match table.get::<_, rlua::Function>("spawn") {
+							Ok(function) => function.call::<(rlua::Table, rlua::Table), ()>((table, object)),
+							Err(_) => Ok(()),
+						}
+					}).map_err(|e| WorldError::lua(&k, e)),
+					None => v.system.write()?.as_native_system_mut().spawn(ctx, id, rlua::Value::Table(object)).map_err(|e| e.in_system(&k)),
+				};
+				if result.is_err() {
+					//nothing was stored for this one, so only the membership has to go
+					v.members.write()?.remove(id);
+					return result;
 				}
 			}
 		}
+		Ok(())
 	}
 
-	pub fn system_update<'lua>(&self, ctx: rlua::Context<'lua>, components: rlua::Table<'lua>, setter: rlua::Function<'lua>) {
-		//idea: go through components, copy into closure, get them back out, put them back into systems
-		'entities: for entity in 0..self.base_id.load(Ordering::SeqCst) {
-			let mut entity_components = rlua::Variadic::new();
-
-			//make sure entity has every component, put them into their own vec
-			//clone it because we want to reuse it for the next entity
-			for pair in components.clone().pairs::<usize, String>() {
-				if let Ok((_, name)) = pair {
-					match self.get(ctx, name, entity) {
-						rlua::Value::Table(component) => {
-							entity_components.push(component);
-						},
-						//if entity doesn't have any one of the components, skip it
-						_ => continue 'entities
-					}
+	//the entity is gone from every system even if some of their despawn hooks fail, the errors get returned together
+	pub fn despawn(&self, ctx: rlua::Context, entity: Entity) -> Result<(), WorldError> {
+		if !self.entities.write()?.free(entity) {
+			return Ok(());
+		}
+		let mut errors = Vec::new();
+		for (k, v) in self.entries()? {
+			let result = (|| -> Result<(), WorldError> {
+				let slot = match v.members.write()?.remove(entity) {
+					Some(slot) => slot,
+					None => return Ok(()),
+				};
+				match v.lua_table(ctx, &k)? {
+					Some(table) => if let Ok(function) = table.get::<_, rlua::Function>("despawn") {
+						function.call::<(rlua::Table, Entity), ()>((table, entity)).map_err(|e| WorldError::lua(&k, e))?;
+					},
+					None => v.system.write()?.as_native_system_mut().despawn(slot),
 				}
+				Ok(())
+			})();
+			if let Err(e) = result {
+				errors.push(e);
 			}
+		}
+		match errors.len() {
+			0 => Ok(()),
+			1 => Err(errors.remove(0)),
+			_ => Err(WorldError::Multiple(errors)),
+		}
+	}
 
-			//call the function here (accepts a variadic, returns variadic)
-			let returns = setter.call::<rlua::Variadic<rlua::Table>, rlua::Value>(entity_components.clone());
+	pub fn get<'lua>(&self, ctx: rlua::Context<'lua>, name: String, entity: Entity) -> Result<rlua::Value<'lua>, WorldError> {
+		let entry = self.entry(&name)?;
+		if !self.is_alive(entity) {
+			return Ok(rlua::Value::Nil);
+		}
+		let slot = match entry.members.read()?.slot(entity) {
+			Some(slot) => slot,
+			None => return Ok(rlua::Value::Nil),
+		};
+		match entry.lua_table(ctx, &name)? {
+			Some(table) => match table.get::<_, rlua::Function>("get") {
+				Ok(function) => function.call::<(rlua::Table, Entity), rlua::Value>((table, entity)).map_err(|e| WorldError::lua(&name, e)),
+				Err(_) => Ok(rlua::Value::Nil),
+			},
+			None => {
+				let system = entry.system.read()?;
+				system.as_native_system().get(ctx, slot).map_err(|e| e.in_system(&name))
+			},
+		}
+	}
+	pub fn set<'lua>(&self, ctx: rlua::Context<'lua>, name: String, entity: Entity, value: rlua::Value<'lua>) -> Result<(), WorldError> {
+		let entry = self.entry(&name)?;
+		if !self.is_alive(entity) {
+			return Ok(());
+		}
+		let slot = match entry.members.read()?.slot(entity) {
+			Some(slot) => slot,
+			None => return Ok(()),
+		};
+		match entry.lua_table(ctx, &name)? {
+			Some(table) => match table.get::<_, rlua::Function>("set") {
+				Ok(function) => function.call::<(rlua::Table, Entity, rlua::Value), ()>((table, entity, value)).map_err(|e| WorldError::lua(&name, e)),
+				Err(_) => Ok(()),
+			},
+			None => {
+				let mut system = entry.system.write()?;
+				system.as_native_system_mut().set(ctx, slot, value).map_err(|e| e.in_system(&name))
+			},
+		}
+	}
 
-			//now put them back, consuming the variadic in the process
-			for (i, table) in returns.into_iter().enumerate() {
-				self.set(ctx, components.get(i+1).unwrap(), entity, table);
-			}
+	pub fn system_update<'lua>(&self, ctx: rlua::Context<'lua>, components: rlua::Table<'lua>, setter: rlua::Function<'lua>) -> Result<(), WorldError> {
+		//idea: go through components, copy into closure, get them back out, put them back into systems
+		let names = lua_names(components.clone());
+		for entity in self.query(&names)? {
+			self.update_components(ctx, entity, &names, &setter)?;
 		}
+		Ok(())
 	}
 
-	pub fn entity_update<'lua>(&self, ctx: rlua::Context<'lua>, entity: usize, components: rlua::Table<'lua>, setter: rlua::Function<'lua>) {
+	pub fn entity_update<'lua>(&self, ctx: rlua::Context<'lua>, entity: Entity, components: rlua::Table<'lua>, setter: rlua::Function<'lua>) -> Result<(), WorldError> {
 		//same idea as system_update, except instead of skipping entities, we just exit immediately.
+		let names = lua_names(components.clone());
+		if !self.has_all(entity, &names)? {
+			return Ok(());
+		}
+		self.update_components(ctx, entity, &names, &setter)
+	}
+
+	fn update_components<'lua>(&self, ctx: rlua::Context<'lua>, entity: Entity, names: &[String], setter: &rlua::Function<'lua>) -> Result<(), WorldError> {
 		let mut entity_components = rlua::Variadic::new();
 
 		//make sure entity has every component, put them into their own vec
-		//clone it because we want to reuse it for the next entity
-		for pair in components.clone().pairs::<usize, String>() {
-			if let Ok((_, name)) = pair {
-				match self.get(ctx, name, entity) {
-					rlua::Value::Table(component) => {
-						entity_components.push(component);
-					},
-					//if entity doesn't have any one of the components, skip it
-					_ => return
-				}
+		for name in names {
+			match self.get(ctx, name.clone(), entity)? {
+				rlua::Value::Table(component) => {
+					entity_components.push(component);
+				},
+				//a Lua system without get() has nothing to hand out
+				_ => return Ok(())
 			}
 		}
 
 		//call the function here (accepts a variadic, returns variadic)
-		let returns = setter.call::<rlua::Variadic<rlua::Table>, rlua::Value>(entity_components.clone());
+		//an error is put down to the systems it was updating, e.g. "Physics, Render: ..."
+		let returns = setter.call::<rlua::Variadic<rlua::Table>, rlua::Variadic<rlua::Value>>(entity_components)
+			.map_err(|e| WorldError::lua(&names.join(", "), e))?;
 
 		//now put them back, consuming the variadic in the process
-		for (i, table) in returns.into_iter().enumerate() {
-			self.set(ctx, components.get(i+1).unwrap(), entity, table);
+		for (name, value) in names.iter().zip(returns) {
+			self.set(ctx, name.clone(), entity, value)?;
+		}
+		Ok(())
+	}
+
+	pub fn add_native_system(&self, ctx: rlua::Context, system: Box<dyn AnyNativeSystem>, object_name: &str, options: SystemOptions) -> Result<(), WorldError> {
+		self.schedule.write()?.add(object_name, options)?;
+		let entry = Arc::new(SystemEntry::new(System::NativeSys(system)));
+		let registered = (|| -> Result<(), WorldError> {
+			let api = LuaApi{ctx, table: ctx.create_table().map_err(|e| WorldError::lua(object_name, e))?, entry: entry.clone()};
+			let system = entry.system.read()?;
+			system.as_native_system().lua_api(&api).map_err(|e| WorldError::lua(object_name, e))?;
+			ctx.globals().set(object_name, api.table).map_err(|e| WorldError::lua(object_name, e))?;
+			//globals go second so they can refer to the system's table
+			system.as_native_system().globals(ctx).map_err(|e| WorldError::lua(object_name, e))?;
+			Ok(())
+		})();
+		if registered.is_err() {
+			self.schedule.write()?.remove(object_name);
+			return registered;
 		}
+		self.systems.write()?.insert(object_name.to_string(), entry);
+		Ok(())
 	}
 
-	pub fn add_native_system(&self, ctx: rlua::Context, mut system: Box<AnyNativeSystem>, system_name: &str, object_name: &str) {
-		system.globals(ctx);
-		self.systems.write().unwrap().insert(object_name.to_string(), std::sync::RwLock::new(System::NativeSys(system)));
-		//self.systems[&object_name.to_string()].write().unwrap().globals(ctx);
-		//self.system_names.insert(object_name.to_string(), system_name.to_string())
+	//one field of one entity, the same thing a link would copy; None if it isn't a member or there's no such field
+	pub fn read_field(&self, object_name: &str, entity: Entity, field: &str) -> Result<Option<Field>, WorldError> {
+		let entry = self.systems.read()?.get(object_name).cloned().ok_or_else(|| WorldError::UnknownSystem(object_name.to_string()))?;
+		let slot = match entry.members.read()?.slot(entity) {
+			Some(slot) => slot,
+			None => return Ok(None),
+		};
+		let system = entry.system.read()?;
+		Ok(match *system {
+			System::NativeSys(ref sys) => sys.read_field(slot, field),
+			System::LuaSys(_) => None,
+		})
 	}
 
-	pub fn read_native_system<Sys, F>(&self, object_name: &str, f: F)
-	where Sys: NativeSystem, F: Fn(&Sys) {
-		let systems_guard = self.systems.read().unwrap();
-		let sysguard = systems_guard.get(object_name).unwrap().read().unwrap();
+	pub fn read_native_system<Sys, F>(&self, object_name: &str, mut f: F) -> Result<(), WorldError>
+	where Sys: NativeSystem, F: FnMut(&Sys) {
+		let systems_guard = self.systems.read()?;
+		let sysguard = systems_guard.get(object_name).ok_or_else(|| WorldError::UnknownSystem(object_name.to_string()))?.system.read()?;
 		if let Some(sys) = sysguard.as_native_system().to_system::<Sys>() {
 			f(sys);
 		}
+		Ok(())
 	}
-	pub fn write_native_system<Sys, F>(&self, object_name: &str, mut f: F)
+	pub fn write_native_system<Sys, F>(&self, object_name: &str, mut f: F) -> Result<(), WorldError>
 	where Sys: NativeSystem, F: FnMut(&mut Sys) {
-		let mut systems_guard = self.systems.read().unwrap();
-		let mut sysguard = systems_guard.get(object_name).unwrap().write().unwrap();
+		let systems_guard = self.systems.read()?;
+		let mut sysguard = systems_guard.get(object_name).ok_or_else(|| WorldError::UnknownSystem(object_name.to_string()))?.system.write()?;
 		if let Some(sys) = sysguard.as_native_system_mut().to_system_mut::<Sys>() {
 			f(sys);
 		}
+		Ok(())
 	}
 
-	pub fn add_lua_system<'lua>(&self, ctx: rlua::Context<'lua>, system: rlua::Table<'lua>, system_name: String, object_name: String) {
-		let regkey = ctx.create_registry_value(system).unwrap();
-		self.systems.write().unwrap().insert(object_name, std::sync::RwLock::new(System::LuaSys(regkey)));
+	pub fn add_lua_system<'lua>(&self, ctx: rlua::Context<'lua>, system: rlua::Table<'lua>, system_name: String, object_name: String, mut options: SystemOptions) -> Result<(), WorldError> {
+		//Lua systems can only tick on the thread that owns the Lua state
+		options.access = None;
+		let regkey = ctx.create_registry_value(system).map_err(|e| WorldError::lua(&object_name, e))?;
+		self.schedule.write()?.add(&object_name, options)?;
+		self.systems.write()?.insert(object_name, Arc::new(SystemEntry::new(System::LuaSys(regkey))));
 		//self.system_names.insert(object_name, system_name)
+		Ok(())
 	}
 
-	pub fn save(&self) -> serde_json::Value {
-		//create json object where keys are SYSTEM_NAME and values are System::save()
-		unimplemented!()
+	//json object where keys are the system's name and values are System::save()
+	//Lua systems can define their own save(self), otherwise their table (minus functions) is saved as-is
+	pub fn save(&self, ctx: rlua::Context) -> Result<serde_json::Value, WorldError> {
+		let mut systems = serde_json::Map::new();
+		let mut members = serde_json::Map::new();
+		for (k, v) in self.entries()? {
+			//same as despawn/get: a Lua save hook runs without any of the world's locks held
+			let state = match v.lua_table(ctx, &k)? {
+				Some(table) => {
+					let save = || -> rlua::Result<serde_json::Value> {
+						let state = if let Ok(function) = table.get::<_, rlua::Function>("save") {
+							function.call::<rlua::Table, rlua::Value>(table)?
+						} else {
+							rlua::Value::Table(table)
+						};
+						rlua_serde::from_value(to_plain_lua(ctx, state, 0)?).map_err(rlua::Error::external)
+					};
+					save().map_err(|e| WorldError::lua(&k, e))?
+				},
+				None => v.system.read()?.as_native_system().save().map_err(|e| e.in_system(&k))?,
+			};
+			members.insert(k.clone(), serde_json::to_value(&*v.members.read()?).map_err(WorldError::parse)?);
+			systems.insert(k, state);
+		}
+		Ok(serde_json::json!({
+			"entities": *self.entities.read()?,
+			"systems": systems,
+			"members": members,
+		}))
+	}
+
+	//systems have to be registered already (Lua functions can't be saved), this only restores their state
+	//everything gets parsed before anything changes, so a bad snapshot leaves the world the way it was
+	pub fn load(&self, ctx: rlua::Context, snapshot: &serde_json::Value) -> Result<(), WorldError> {
+		let entities: EntityAllocator = serde_json::from_value(snapshot["entities"].clone()).map_err(|e| WorldError::parse(e).in_system("entities"))?;
+		let mut loaded = Vec::new();
+		for (k, v) in self.entries()? {
+			//a system the snapshot doesn't have gets emptied, or its members would alias whatever the new allocator hands out
+			let state = &snapshot["systems"][&k];
+			let members: Members = match state {
+				serde_json::Value::Null => Members::new(),
+				_ => serde_json::from_value(snapshot["members"][&k].clone()).map_err(|e| WorldError::parse(e).in_system(&k))?,
+			};
+			if let Some(dead) = members.iter().find(|&e| !entities.is_alive(e)) {
+				return Err(WorldError::parse(format!("member {} isn't alive", dead)).in_system(&k));
+			}
+			let state = match *v.system.read()? {
+				System::NativeSys(ref sys) => {
+					let state = if state.is_null() { sys.empty_state().map_err(|e| e.in_system(&k))? } else { state.clone() };
+					//slots are handed out by members, so the two have to line up
+					let slots = sys.check_state(state.clone()).map_err(|e| e.in_system(&k))?;
+					if slots != members.len() {
+						return Err(WorldError::parse(format!("{} slots for {} members", slots, members.len())).in_system(&k));
+					}
+					Loaded::Native(state)
+				},
+				System::LuaSys(_) => Loaded::Lua(match state {
+					serde_json::Value::Null => rlua::Value::Table(ctx.create_table().map_err(|e| WorldError::lua(&k, e))?),
+					state => rlua_serde::to_value(ctx, state).map_err(rlua::Error::external)
+						.and_then(|state| from_plain_lua(ctx, state))
+						.map_err(|e| WorldError::lua(&k, e))?,
+				}),
+			};
+			loaded.push((k, v, members, state));
+		}
+
+		*self.entities.write()? = entities;
+		for (k, v, members, state) in loaded {
+			*v.members.write()? = members;
+			match state {
+				Loaded::Native(state) => v.system.write()?.as_native_system_mut().load(state).map_err(|e| e.in_system(&k))?,
+				Loaded::Lua(state) => {
+					let table = match v.lua_table(ctx, &k)? {
+						Some(table) => table,
+						None => continue,
+					};
+					let load = || -> rlua::Result<()> {
+						if let Ok(function) = table.get::<_, rlua::Function>("load") {
+							function.call::<(rlua::Table, rlua::Value), ()>((table, state))?;
+						} else if let rlua::Value::Table(state) = state {
+							//drop the old data but keep the methods, since those were never saved
+							let mut stale = Vec::new();
+							for pair in table.clone().pairs::<rlua::Value, rlua::Value>() {
+								let (key, value) = pair?;
+								if let rlua::Value::Function(_) = value {
+									continue;
+								}
+								stale.push(key);
+							}
+							for key in stale {
+								table.set(key, rlua::Value::Nil)?;
+							}
+							for pair in state.pairs::<rlua::Value, rlua::Value>() {
+								let (key, value) = pair?;
+								table.set(key, value)?;
+							}
+						}
+						Ok(())
+					};
+					load().map_err(|e| WorldError::lua(&k, e))?
+				},
+			}
+		}
+		Ok(())
+	}
+}
+
+//a system's state out of a snapshot, parsed and ready to go in
+enum Loaded<'lua> {
+	Native(serde_json::Value),
+	Lua(rlua::Value<'lua>),
+}
+
+//for id in world:members(...) do
+//iterates over a snapshot, so despawning while iterating is fine
+fn entity_iter(ctx: rlua::Context, entities: Vec<Entity>) -> rlua::Result<rlua::Function> {
+	let next = std::cell::Cell::new(0);
+	ctx.create_function(move |_, ()| {
+		let i = next.get();
+		next.set(i + 1);
+		Ok(entities.get(i).cloned())
+	})
+}
+
+//{"Physics", "Render"} -> vec!["Physics", "Render"]
+fn lua_names(components: rlua::Table) -> Vec<String> {
+	components.sequence_values::<String>().filter_map(Result::ok).collect()
+}
+
+//deep enough for any sane system table, shallow enough that a cycle won't blow the stack
+const MAX_SAVE_DEPTH: usize = 32;
+
+//marks a table key that was an integer before it went through json
+const INTEGER_KEY: &str = "#";
+
+//copies a Lua value into something json can represent:
+//functions/userdata get dropped, and tables that aren't plain sequences get their keys stringified
+fn to_plain_lua<'lua>(ctx: rlua::Context<'lua>, value: rlua::Value<'lua>, depth: usize) -> rlua::Result<rlua::Value<'lua>> {
+	Ok(match value {
+		rlua::Value::Table(table) => {
+			if depth >= MAX_SAVE_DEPTH {
+				return Ok(rlua::Value::Nil);
+			}
+			let len = table.raw_len();
+			let mut pairs = Vec::new();
+			for pair in table.pairs::<rlua::Value, rlua::Value>() {
+				pairs.push(pair?);
+			}
+			let is_sequence = len > 0 && pairs.len() as i64 == len && pairs.iter().all(|(k, _)| match k {
+				rlua::Value::Integer(i) => *i >= 1 && *i <= len,
+				_ => false,
+			});
+			let plain = ctx.create_table()?;
+			for (k, v) in pairs {
+				let v = to_plain_lua(ctx, v, depth + 1)?;
+				if let rlua::Value::Nil = v {
+					continue;
+				}
+				match k {
+					rlua::Value::Integer(i) if is_sequence => plain.set(i, v)?,
+					//json keys are all strings, so integer ones get tagged, and strings that look tagged get escaped
+					rlua::Value::Integer(i) => plain.set(format!("{}{}", INTEGER_KEY, i), v)?,
+					rlua::Value::String(s) => match s.to_str() {
+						Ok(key) if key.starts_with(INTEGER_KEY) => plain.set(format!("{}{}", INTEGER_KEY, key), v)?,
+						_ => plain.set(s, v)?,
+					},
+					_ => {}
+				}
+			}
+			rlua::Value::Table(plain)
+		},
+		rlua::Value::Function(_) | rlua::Value::UserData(_) | rlua::Value::LightUserData(_) | rlua::Value::Thread(_) | rlua::Value::Error(_) => rlua::Value::Nil,
+		v => v,
+	})
+}
+
+//reverse of to_plain_lua: tagged keys become integer keys again, so tables keyed by entity id survive a round trip
+//("#12" is the integer 12, "##12" is the string "#12", and "12" stays a string)
+fn from_plain_lua<'lua>(ctx: rlua::Context<'lua>, value: rlua::Value<'lua>) -> rlua::Result<rlua::Value<'lua>> {
+	if let rlua::Value::Table(table) = value {
+		let restored = ctx.create_table()?;
+		for pair in table.pairs::<rlua::Value, rlua::Value>() {
+			let (k, v) = pair?;
+			let v = from_plain_lua(ctx, v)?;
+			let tagged = match k {
+				rlua::Value::String(ref s) => s.to_str().ok().and_then(|s| s.strip_prefix(INTEGER_KEY)),
+				_ => None,
+			};
+			match tagged {
+				Some(key) if key.starts_with(INTEGER_KEY) => restored.set(key, v)?,
+				Some(key) => restored.set(key.parse::<i64>().map_err(rlua::Error::external)?, v)?,
+				None => restored.set(k, v)?,
+			}
+		}
+		Ok(rlua::Value::Table(restored))
+	} else {
+		Ok(value)
+	}
+}
+
+//handed to NativeSystem::lua_api, the functions it registers lock the system on every call
+pub struct LuaApi<'lua> {
+	ctx: rlua::Context<'lua>,
+	table: rlua::Table<'lua>,
+	entry: Arc<SystemEntry>,
+}
+
+impl<'lua> LuaApi<'lua> {
+	//members are passed along so entity ids can be turned into slots
+	pub fn add_function<Sys, A, R, F>(&self, name: &str, f: F) -> rlua::Result<()>
+	where Sys: NativeSystem,
+		A: rlua::FromLuaMulti<'lua>,
+		R: rlua::ToLuaMulti<'lua>,
+		F: 'static + Send + Fn(rlua::Context<'lua>, &mut Sys, &Members, A) -> rlua::Result<R> {
+		let entry = self.entry.clone();
+		let function = self.ctx.create_function(move |ctx, args: A| {
+			let members = entry.members.read().map_err(WorldError::from)?;
+			let mut system = entry.system.write().map_err(WorldError::from)?;
+			match system.as_native_system_mut().to_system_mut::<Sys>() {
+				Some(sys) => f(ctx, sys, &members, args),
+				None => Err(rlua::Error::RuntimeError("system type mismatch".to_string())),
+			}
+		})?;
+		self.table.set(name, function)
 	}
 }
 
-use std::sync::Arc;
 pub struct WorldRef(pub Arc<World>);
 impl Clone for WorldRef {
 	fn clone(&self) -> Self {
@@ -246,41 +770,257 @@ impl Clone for WorldRef {
 
 impl WorldRef {
 	//delegates
-	pub fn read_native_system<Sys, F>(&self, object_name: &str, f: F)
-	where Sys: NativeSystem, F: Fn(&Sys) {
-		self.0.read_native_system(object_name, f);
-	}
-	pub fn write_native_system<Sys, F>(&self, object_name: &str, f: F)
+	pub fn write_native_system<Sys, F>(&self, object_name: &str, f: F) -> Result<(), WorldError>
 	where Sys: NativeSystem, F: FnMut(&mut Sys) {
-		self.0.write_native_system(object_name, f);
+		self.0.write_native_system(object_name, f)
+	}
+	pub fn read_native_system<Sys, F>(&self, object_name: &str, f: F) -> Result<(), WorldError>
+	where Sys: NativeSystem, F: FnMut(&Sys) {
+		self.0.read_native_system(object_name, f)
 	}
-	pub fn size(&self) -> usize {
-		self.0.base_id.load(Ordering::SeqCst)
+	pub fn members(&self, name: &str) -> Result<Vec<Entity>, WorldError> {
+		self.0.members(name)
 	}
 }
 
 impl rlua::UserData for WorldRef {
 	fn add_methods<'lua, M: rlua::UserDataMethods<'lua, Self>>(methods: &mut M) {
 		methods.add_method("spawn", |ctx, this, components: rlua::Table| {
-			Ok(this.0.spawn(ctx, components))
+			Ok(this.0.spawn(ctx, components)?)
+		});
+		methods.add_method("despawn", |ctx, this, entity: Entity| {
+			Ok(this.0.despawn(ctx, entity)?)
+		});
+		methods.add_method("alive", |_, this, entity: Entity| {
+			Ok(this.0.is_alive(entity))
+		});
+		methods.add_method("members", |ctx, this, name: String| {
+			entity_iter(ctx, this.0.members(&name)?)
 		});
-		methods.add_method("get", |ctx, this, (name, entity): (String, usize)| {
-			Ok(this.0.get(ctx, name, entity))
+		methods.add_method("query", |ctx, this, components: rlua::Table| {
+			entity_iter(ctx, this.0.query(&lua_names(components))?)
+		});
+		methods.add_method("get", |ctx, this, (name, entity): (String, Entity)| {
+			Ok(this.0.get(ctx, name, entity)?)
 		});
 		methods.add_method("system_update", |ctx, this, (components, setter): (rlua::Table, rlua::Function)| {
-			Ok(this.0.system_update(ctx, components, setter))
+			Ok(this.0.system_update(ctx, components, setter)?)
+		});
+		methods.add_method("entity_update", |ctx, this, (entity, components, setter): (Entity, rlua::Table, rlua::Function)| {
+			Ok(this.0.entity_update(ctx, entity, components, setter)?)
+		});
+		methods.add_method("add_system", |ctx, this, (system, system_name, object_name, options): (rlua::Table, String, String, SystemOptions)| {
+			Ok(this.0.add_lua_system(ctx, system, system_name, object_name, options)?)
 		});
-		methods.add_method("entity_update", |ctx, this, (entity, components, setter): (usize, rlua::Table, rlua::Function)| {
-			Ok(this.0.entity_update(ctx, entity, components, setter))
+		methods.add_method("schedule", |_, this, ()| {
+			Ok(this.0.schedule.read().map_err(WorldError::from)?.order().to_vec())
 		});
-		methods.add_method("add_system", |ctx, this, (system, system_name, object_name): (rlua::Table, String, String)| {
-			Ok(this.0.add_lua_system(ctx, system, system_name, object_name))
+		methods.add_method("tick", |ctx, this, dt: f64| {
+			Ok(this.0.tick(ctx, dt)?)
 		});
-		methods.add_method("tick", |ctx, this, ()| {
-			Ok(this.0.tick(ctx))
+		methods.add_method("save", |ctx, this, ()| {
+			Ok(this.0.save(ctx)?.to_string())
+		});
+		methods.add_method("load", |ctx, this, snapshot: String| {
+			let snapshot: serde_json::Value = serde_json::from_str(&snapshot).map_err(WorldError::parse)?;
+			Ok(this.0.load(ctx, &snapshot)?)
 		});
 		methods.add_method("size", |_, this, ()| {
-			Ok(this.0.base_id.load(Ordering::SeqCst))
+			Ok(this.0.entities.read().map_err(WorldError::from)?.len())
+		});
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tilemap::TilemapSystem;
+
+	fn world(ctx: rlua::Context) -> World {
+		let world = World::new();
+		world.add_native_system(ctx, Box::new(TilemapSystem::new()), "Tilemap", SystemOptions::new()).unwrap();
+		let inventory: rlua::Table = ctx.load("{items = {}}").eval().unwrap();
+		world.add_lua_system(ctx, inventory, "InventorySystem".to_string(), "Inventory".to_string(), SystemOptions::new()).unwrap();
+		world
+	}
+
+	fn spawn_map(ctx: rlua::Context, world: &World) -> Entity {
+		let components = ctx.load("{Tilemap = {tileset = 'tiles', tile_width = 8, tile_height = 8, tiles = {{1, 0}}}}").eval().unwrap();
+		world.spawn(ctx, components).unwrap()
+	}
+
+	#[test]
+	fn lua_hooks_can_use_the_world_on_their_own_system() {
+		rlua::Lua::new().context(|ctx| {
+			ctx.globals().set("world", WorldRef(Arc::new(world(ctx)))).unwrap();
+			ctx.load(r#"
+				local Tag = {names = {}}
+				function Tag:spawn(object)
+					self.names[object.id] = object.name
+					assert(world:get("Tag", object.id).name == object.name)
+				end
+				function Tag:get(id)
+					for _ in world:members("Tag") do end
+					return {name = self.names[id]}
+				end
+				function Tag:set(id, tag)
+					assert(world:get("Tag", id))
+					self.names[id] = tag.name
+				end
+				function Tag:despawn(id)
+					for _ in world:members("Tag") do end
+					self.names[id] = nil
+				end
+				world:add_system(Tag, "TagSystem", "Tag")
+				local id = world:spawn({Tag = {name = "a"}})
+				world:entity_update(id, {"Tag"}, function(tag) tag.name = tag.name .. "b" return tag end)
+				assert(world:get("Tag", id).name == "ab")
+				world:despawn(id)
+				assert(Tag.names[id] == nil)
+			"#).exec().unwrap();
+		});
+	}
+
+	#[test]
+	fn save_hooks_run_without_the_world_locked() {
+		rlua::Lua::new().context(|ctx| {
+			let world = Arc::new(world(ctx));
+			let unlocked = world.clone();
+			ctx.globals().set("unlocked", ctx.create_function(move |_, ()| {
+				let systems = unlocked.systems.try_write().is_ok();
+				let inventory = unlocked.entry("Inventory")?;
+				let system = inventory.system.try_write().is_ok();
+				Ok(systems && system)
+			}).unwrap()).unwrap();
+			let inventory = world.entry("Inventory").unwrap().lua_table(ctx, "Inventory").unwrap().unwrap();
+			inventory.set("save", ctx.load("function(self) assert(unlocked()) return {items = self.items} end").eval::<rlua::Function>().unwrap()).unwrap();
+			world.save(ctx).unwrap();
+		});
+	}
+
+	#[test]
+	fn failing_despawn_hooks_dont_keep_the_entity_around() {
+		rlua::Lua::new().context(|ctx| {
+			let world = world(ctx);
+			for name in &["Broken", "AlsoBroken"] {
+				let system = ctx.load("{despawn = function(self, id) error('no') end}").eval().unwrap();
+				world.add_lua_system(ctx, system, name.to_string(), name.to_string(), SystemOptions::new()).unwrap();
+			}
+			let components = ctx.load("{Tilemap = {tileset = 'tiles', tile_width = 8, tile_height = 8}, Broken = {}, AlsoBroken = {}}").eval().unwrap();
+			let entity = world.spawn(ctx, components).unwrap();
+			match world.despawn(ctx, entity) {
+				Err(WorldError::Multiple(errors)) => {
+					let mut systems: Vec<String> = errors.into_iter().map(|e| match e {
+						WorldError::Lua{system, ..} => system,
+						e => panic!("expected a Lua error, got {:?}", e),
+					}).collect();
+					systems.sort();
+					assert_eq!(systems, vec!["AlsoBroken", "Broken"]);
+				},
+				result => panic!("expected both hooks to fail, got {:?}", result),
+			}
+			assert!(!world.is_alive(entity));
+			for name in &["Tilemap", "Broken", "AlsoBroken"] {
+				assert!(world.members(name).unwrap().is_empty(), "{} still has it", name);
+			}
+			world.read_native_system("Tilemap", |t: &TilemapSystem| assert!(t.maps.is_empty())).unwrap();
+		});
+	}
+
+	#[test]
+	fn setter_errors_name_the_systems_being_updated() {
+		rlua::Lua::new().context(|ctx| {
+			let world = world(ctx);
+			let map = spawn_map(ctx, &world);
+			let setter = ctx.load("function(map) return map .. map end").eval().unwrap();
+			match world.entity_update(ctx, map, ctx.load("{'Tilemap'}").eval().unwrap(), setter) {
+				Err(WorldError::Lua{system, ..}) => assert_eq!(system, "Tilemap"),
+				result => panic!("expected a Lua error, got {:?}", result),
+			}
+		});
+	}
+
+	#[test]
+	fn integer_and_string_keys_survive_a_round_trip() {
+		rlua::Lua::new().context(|ctx| {
+			let table: rlua::Value = ctx.load(r##"{[1] = "one", ["1"] = "string one", [5] = "five", ["#2"] = "hash", name = "name"}"##).eval().unwrap();
+			let json: serde_json::Value = rlua_serde::from_value(to_plain_lua(ctx, table, 0).unwrap()).unwrap();
+			let restored = from_plain_lua(ctx, rlua_serde::to_value(ctx, &json).unwrap()).unwrap();
+			let restored = match restored {
+				rlua::Value::Table(table) => table,
+				_ => panic!("expected a table"),
+			};
+			assert_eq!(restored.get::<_, String>(1).unwrap(), "one");
+			assert_eq!(restored.get::<_, String>("1").unwrap(), "string one");
+			assert_eq!(restored.get::<_, String>(5).unwrap(), "five");
+			assert_eq!(restored.get::<_, String>("#2").unwrap(), "hash");
+			assert_eq!(restored.get::<_, String>("name").unwrap(), "name");
+			assert_eq!(restored.pairs::<rlua::Value, rlua::Value>().count(), 5);
+		});
+	}
+
+	#[test]
+	fn bad_snapshot_leaves_the_world_alone() {
+		rlua::Lua::new().context(|ctx| {
+			let world = world(ctx);
+			let map = spawn_map(ctx, &world);
+			let mut snapshot = world.save(ctx).unwrap();
+			spawn_map(ctx, &world);
+			snapshot["systems"]["Tilemap"]["maps"] = serde_json::json!("not a list of maps");
+			assert!(world.load(ctx, &snapshot).is_err());
+			assert_eq!(world.entities().len(), 2);
+			assert_eq!(world.members("Tilemap").unwrap().len(), 2);
+			assert!(world.is_alive(map));
+		});
+	}
+
+	#[test]
+	fn tampered_snapshots_dont_load() {
+		rlua::Lua::new().context(|ctx| {
+			let world = world(ctx);
+			let map = spawn_map(ctx, &world);
+			let gone = spawn_map(ctx, &world);
+			world.despawn(ctx, gone).unwrap();
+			let snapshot = world.save(ctx).unwrap();
+			world.load(ctx, &snapshot).unwrap();
+			type Tamper = fn(&mut serde_json::Value);
+			let tampered: Vec<(&str, Tamper)> = vec![
+				//one tile short of what the map's size says
+				("Tilemap", |s| { s["systems"]["Tilemap"]["maps"][0]["tiles"].as_array_mut().unwrap().pop(); }),
+				//the same member twice, and a map without a member
+				("Tilemap", |s| { let map = s["members"]["Tilemap"][0].clone(); s["members"]["Tilemap"].as_array_mut().unwrap().push(map); }),
+				("Tilemap", |s| s["members"]["Tilemap"] = serde_json::json!([])),
+				//the despawned map, which isn't alive anymore
+				("Tilemap", |s| s["members"]["Tilemap"][0] = serde_json::json!(Entity{index: 1, generation: 0})),
+				//the live entity on the free list, and the generations not lining up with who's alive
+				("entities", |s| s["entities"]["free"] = serde_json::json!([0])),
+				("entities", |s| { s["entities"]["generations"].as_array_mut().unwrap().pop(); }),
+			];
+			for (i, (name, tamper)) in tampered.into_iter().enumerate() {
+				let mut snapshot = snapshot.clone();
+				tamper(&mut snapshot);
+				match world.load(ctx, &snapshot) {
+					Err(WorldError::Parse{system, ..}) => assert_eq!(system, name, "tampering {}", i),
+					result => panic!("tampering {} gave {:?}", i, result),
+				}
+				assert_eq!(world.members("Tilemap").unwrap(), vec![map]);
+			}
+		});
+	}
+
+	#[test]
+	fn systems_missing_from_the_snapshot_get_emptied() {
+		rlua::Lua::new().context(|ctx| {
+			let world = world(ctx);
+			let mut snapshot = world.save(ctx).unwrap();
+			snapshot["systems"].as_object_mut().unwrap().remove("Tilemap");
+			spawn_map(ctx, &world);
+			world.load(ctx, &snapshot).unwrap();
+			assert!(world.members("Tilemap").unwrap().is_empty());
+			world.read_native_system("Tilemap", |t: &TilemapSystem| assert!(t.maps.is_empty())).unwrap();
+			//the index the old map had gets handed out again without anything still pointing at it
+			let map = spawn_map(ctx, &world);
+			assert_eq!(world.members("Tilemap").unwrap(), vec![map]);
 		});
 	}
 }